@@ -0,0 +1,40 @@
+--[[
+FILE: overlays/stage_select_actor3/competitive.lua
+Notes: The tourney tweak of stage_select_actor3.hdr.tourney.lua as an overlay. Copy this to
+sd:/ultimate/stage-alts/overlays/stage_select_actor3/ to only offer alts that are tagged as
+tournament legal on any stage select script.
+
+An overlay returns a table keyed by the names of the script's local functions. Each value
+gets the script's function and returns the one that replaces it.
+
+The tag filter is shared by every stage select, so it is only set while this one is open: it
+is set again in every setup and cleared when the player backs out. The plugin also clears it
+when leaving local play.
+]]
+--
+
+local function can_filter()
+    return Alts.has ~= nil and Alts.has("set_tag_filter")
+end
+
+return {
+    setup = function(original)
+        return function(...)
+            original(...)
+
+            if can_filter() then
+                Alts.set_tag_filter("competitive")
+            end
+        end
+    end,
+
+    cancel = function(original)
+        return function(...)
+            original(...)
+
+            if can_filter() then
+                Alts.set_tag_filter(nil)
+            end
+        end
+    end
+}
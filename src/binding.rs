@@ -0,0 +1,490 @@
+//! Typed bindings between Lua and the functions the plugin exposes to it. Arguments are read
+//! and checked before a function runs, and bad ones raise a Lua error naming the argument
+//! instead of being cast into nonsense. Only the Lua C API is used here, so this builds
+//! against a host Lua 5.3 as well as the game's
+
+use std::ffi::CStr;
+
+use rlua_lua53_sys as lua;
+
+pub type State = *mut lua::lua_State;
+
+/// A function that can be registered in a Lua table, see [`lua_functions`]
+pub struct Function {
+    /// Nul terminated so it can be handed to Lua as is
+    pub name: &'static str,
+    pub func: unsafe extern "C" fn(State) -> i32,
+}
+
+impl Function {
+    pub fn name(&self) -> &'static str {
+        self.name.trim_end_matches('\0')
+    }
+}
+
+/// Builds a `&[Function]` from plain Rust functions whose arguments implement [`FromLua`]
+/// and whose return value implements [`IntoLua`]
+#[macro_export]
+macro_rules! lua_functions {
+    ($($name:ident),* $(,)?) => {
+        &[$(
+            $crate::binding::Function {
+                name: concat!(stringify!($name), "\0"),
+                func: {
+                    unsafe extern "C" fn wrapper(state: $crate::binding::State) -> i32 {
+                        $crate::binding::call(state, stringify!($name), $name)
+                    }
+                    wrapper
+                },
+            }
+        ),*]
+    };
+}
+
+/// Why the arguments of a call couldn't be read
+pub enum CallError {
+    TooManyArguments {
+        max: i32,
+        got: i32,
+    },
+    BadArgument {
+        /// 1 based, like Lua's own error messages
+        position: i32,
+        expected: &'static str,
+        got: &'static str,
+    },
+}
+
+impl CallError {
+    fn message(&self, name: &str) -> String {
+        match self {
+            Self::TooManyArguments { max, got } => {
+                format!("'{name}' takes at most {max} arguments, got {got}")
+            }
+            Self::BadArgument {
+                position,
+                expected,
+                got,
+            } => format!("bad argument #{position} to '{name}' ({expected} expected, got {got})"),
+        }
+    }
+}
+
+/// The name of the type of the value at a stack index, `no value` past the top
+pub unsafe fn type_name(state: State, index: i32) -> &'static str {
+    CStr::from_ptr(lua::lua_typename(state, lua::lua_type(state, index)))
+        .to_str()
+        .unwrap_or("?")
+}
+
+pub trait FromLua: Sized {
+    /// Reads the value at a stack index, returning the name of the expected type if the value
+    /// doesn't fit
+    unsafe fn from_lua(state: State, index: i32) -> Result<Self, &'static str>;
+}
+
+impl FromLua for i64 {
+    unsafe fn from_lua(state: State, index: i32) -> Result<Self, &'static str> {
+        if lua::lua_isinteger(state, index) == 0 {
+            return Err("integer");
+        }
+
+        Ok(lua::lua_tointegerx(state, index, std::ptr::null_mut()))
+    }
+}
+
+impl FromLua for usize {
+    unsafe fn from_lua(state: State, index: i32) -> Result<Self, &'static str> {
+        let value = i64::from_lua(state, index)?;
+        usize::try_from(value).map_err(|_| "non-negative integer")
+    }
+}
+
+/// Follows Lua's truthiness, only `nil` and `false` (or a missing argument) are false
+impl FromLua for bool {
+    unsafe fn from_lua(state: State, index: i32) -> Result<Self, &'static str> {
+        Ok(lua::lua_toboolean(state, index) != 0)
+    }
+}
+
+/// Only actual strings, unlike `lua_isstring` numbers aren't converted
+impl FromLua for String {
+    unsafe fn from_lua(state: State, index: i32) -> Result<Self, &'static str> {
+        if lua::lua_type(state, index) != lua::LUA_TSTRING {
+            return Err("string");
+        }
+
+        let mut len = 0;
+        let ptr = lua::lua_tolstring(state, index, &mut len);
+        let bytes = std::slice::from_raw_parts(ptr as *const u8, len);
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+/// `nil` or a missing argument
+impl<T: FromLua> FromLua for Option<T> {
+    unsafe fn from_lua(state: State, index: i32) -> Result<Self, &'static str> {
+        if lua::lua_type(state, index) <= lua::LUA_TNIL {
+            Ok(None)
+        } else {
+            T::from_lua(state, index).map(Some)
+        }
+    }
+}
+
+pub trait IntoLua {
+    /// Pushes the value and returns how many values were pushed
+    unsafe fn push(self, state: State) -> i32;
+}
+
+impl IntoLua for () {
+    unsafe fn push(self, _: State) -> i32 {
+        0
+    }
+}
+
+impl IntoLua for bool {
+    unsafe fn push(self, state: State) -> i32 {
+        lua::lua_pushboolean(state, self as i32);
+        1
+    }
+}
+
+macro_rules! impl_into_lua_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl IntoLua for $ty {
+                unsafe fn push(self, state: State) -> i32 {
+                    lua::lua_pushinteger(state, self as i64);
+                    1
+                }
+            }
+        )*
+    };
+}
+
+impl_into_lua_integer!(i64, u64, usize, u32);
+
+impl IntoLua for &str {
+    unsafe fn push(self, state: State) -> i32 {
+        lua::lua_pushlstring(state, self.as_ptr() as _, self.len());
+        1
+    }
+}
+
+impl IntoLua for &String {
+    unsafe fn push(self, state: State) -> i32 {
+        self.as_str().push(state)
+    }
+}
+
+impl IntoLua for String {
+    unsafe fn push(self, state: State) -> i32 {
+        self.as_str().push(state)
+    }
+}
+
+/// `None` is pushed as `nil`
+impl<T: IntoLua> IntoLua for Option<T> {
+    unsafe fn push(self, state: State) -> i32 {
+        match self {
+            Some(value) => value.push(state),
+            None => {
+                lua::lua_pushnil(state);
+                1
+            }
+        }
+    }
+}
+
+/// Pushed as a sequence, each element must push a single value
+impl<T: IntoLua> IntoLua for Vec<T> {
+    unsafe fn push(self, state: State) -> i32 {
+        lua::lua_createtable(state, self.len() as i32, 0);
+        for (index, value) in self.into_iter().enumerate() {
+            value.push(state);
+            lua::lua_rawseti(state, -2, index as i64 + 1);
+        }
+        1
+    }
+}
+
+impl<'a, T> IntoLua for &'a [T]
+where
+    &'a T: IntoLua,
+{
+    unsafe fn push(self, state: State) -> i32 {
+        lua::lua_createtable(state, self.len() as i32, 0);
+        for (index, value) in self.iter().enumerate() {
+            value.push(state);
+            lua::lua_rawseti(state, -2, index as i64 + 1);
+        }
+        1
+    }
+}
+
+/// Tuples are pushed as multiple return values
+macro_rules! impl_into_lua_tuple {
+    ($($ty:ident),+) => {
+        impl<$($ty: IntoLua),+> IntoLua for ($($ty,)+) {
+            #[allow(non_snake_case)]
+            unsafe fn push(self, state: State) -> i32 {
+                let ($($ty,)+) = self;
+                0 $(+ $ty.push(state))+
+            }
+        }
+    };
+}
+
+impl_into_lua_tuple!(A, B);
+impl_into_lua_tuple!(A, B, C);
+impl_into_lua_tuple!(A, B, C, D);
+
+/// Sets a field of the table on top of the stack, `key` must be nul terminated
+pub unsafe fn set_field<T: IntoLua>(state: State, key: &'static str, value: T) {
+    value.push(state);
+    lua::lua_setfield(state, -2, key.as_ptr() as _);
+}
+
+/// A Rust function that can be called with arguments read from the Lua stack
+pub trait Callable<Args> {
+    unsafe fn call_lua(self, state: State) -> Result<i32, CallError>;
+}
+
+macro_rules! impl_callable {
+    ($($arg:ident),*) => {
+        impl<F, R, $($arg),*> Callable<($($arg,)*)> for F
+        where
+            F: FnOnce($($arg),*) -> R,
+            R: IntoLua,
+            $($arg: FromLua),*
+        {
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            unsafe fn call_lua(self, state: State) -> Result<i32, CallError> {
+                let max = <[&str]>::len(&[$(stringify!($arg)),*]) as i32;
+                let got = lua::lua_gettop(state);
+                if got > max {
+                    return Err(CallError::TooManyArguments { max, got });
+                }
+
+                let mut position = 0;
+                $(
+                    position += 1;
+                    let $arg = $arg::from_lua(state, position).map_err(|expected| {
+                        CallError::BadArgument {
+                            position,
+                            expected,
+                            got: type_name(state, position),
+                        }
+                    })?;
+                )*
+
+                Ok(self($($arg),*).push(state))
+            }
+        }
+    };
+}
+
+impl_callable!();
+impl_callable!(A);
+impl_callable!(A, B);
+impl_callable!(A, B, C);
+impl_callable!(A, B, C, D);
+impl_callable!(A, B, C, D, E);
+impl_callable!(A, B, C, D, E, G);
+impl_callable!(A, B, C, D, E, G, H);
+impl_callable!(A, B, C, D, E, G, H, I);
+impl_callable!(A, B, C, D, E, G, H, I, J);
+
+/// Calls a typed function with the arguments on the Lua stack, raising a Lua error if they
+/// don't fit its signature
+pub unsafe fn call<Args, F: Callable<Args>>(state: State, name: &str, function: F) -> i32 {
+    // lua_error doesn't return, so nothing may be left to drop when it is called
+    match function.call_lua(state) {
+        Ok(count) => return count,
+        Err(error) => {
+            let message = error.message(name);
+            log::error!("{message}");
+            message.as_str().push(state);
+        }
+    }
+
+    lua::lua_error(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(a: i64, b: Option<i64>) -> i64 {
+        a + b.unwrap_or(0)
+    }
+
+    fn repeat(text: String, count: usize) -> String {
+        text.repeat(count)
+    }
+
+    static FUNCTIONS: &[Function] = crate::lua_functions![add, repeat];
+
+    fn function(name: &str) -> &'static Function {
+        FUNCTIONS
+            .iter()
+            .find(|function| function.name() == name)
+            .unwrap()
+    }
+
+    struct Lua(State);
+
+    impl Lua {
+        fn new() -> Self {
+            Self(unsafe { lua::luaL_newstate() })
+        }
+
+        /// Calls a function with the arguments pushed by `args`, leaving its results on an
+        /// otherwise empty stack or returning the error it raised
+        fn call(&self, name: &str, args: impl FnOnce(State)) -> Result<(), String> {
+            unsafe {
+                lua::lua_settop(self.0, 0);
+                lua::lua_pushcclosure(self.0, Some(function(name).func), 0);
+                args(self.0);
+
+                let count = lua::lua_gettop(self.0) - 1;
+                if lua::lua_pcall(self.0, count, lua::LUA_MULTRET, 0) != lua::LUA_OK {
+                    return Err(String::from_lua(self.0, -1).unwrap());
+                }
+            }
+
+            Ok(())
+        }
+
+        fn result<T: FromLua>(&self) -> T {
+            unsafe { T::from_lua(self.0, 1).ok().unwrap() }
+        }
+    }
+
+    impl Drop for Lua {
+        fn drop(&mut self) {
+            unsafe { lua::lua_close(self.0) }
+        }
+    }
+
+    unsafe fn integer(state: State, value: i64) {
+        lua::lua_pushinteger(state, value);
+    }
+
+    unsafe fn string(state: State, value: &str) {
+        value.push(state);
+    }
+
+    #[test]
+    fn calls_with_arguments() {
+        let lua = Lua::new();
+
+        lua.call("add", |state| unsafe {
+            integer(state, 1);
+            integer(state, 2);
+        })
+        .unwrap();
+        assert_eq!(lua.result::<i64>(), 3);
+
+        lua.call("repeat", |state| unsafe {
+            string(state, "ab");
+            integer(state, 2);
+        })
+        .unwrap();
+        assert_eq!(lua.result::<String>(), "abab");
+    }
+
+    #[test]
+    fn missing_optional_arguments_are_none() {
+        let lua = Lua::new();
+
+        lua.call("add", |state| unsafe { integer(state, 1) })
+            .unwrap();
+        assert_eq!(lua.result::<i64>(), 1);
+
+        lua.call("add", |state| unsafe {
+            integer(state, 1);
+            lua::lua_pushnil(state);
+        })
+        .unwrap();
+        assert_eq!(lua.result::<i64>(), 1);
+    }
+
+    #[test]
+    fn rejects_extra_arguments() {
+        let lua = Lua::new();
+
+        let error = lua.call("add", |state| unsafe {
+            integer(state, 1);
+            integer(state, 2);
+            integer(state, 3);
+        });
+        assert_eq!(error.unwrap_err(), "'add' takes at most 2 arguments, got 3");
+    }
+
+    #[test]
+    fn rejects_missing_arguments() {
+        let lua = Lua::new();
+
+        let error = lua.call("repeat", |state| unsafe { string(state, "a") });
+        assert_eq!(
+            error.unwrap_err(),
+            "bad argument #2 to 'repeat' (integer expected, got no value)"
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_types() {
+        let lua = Lua::new();
+
+        let error = lua.call("add", |state| unsafe { string(state, "1") });
+        assert_eq!(
+            error.unwrap_err(),
+            "bad argument #1 to 'add' (integer expected, got string)"
+        );
+
+        let error = lua.call("add", |state| unsafe { lua::lua_pushnumber(state, 1.5) });
+        assert_eq!(
+            error.unwrap_err(),
+            "bad argument #1 to 'add' (integer expected, got number)"
+        );
+
+        let error = lua.call("add", |state| unsafe {
+            integer(state, 1);
+            lua::lua_pushboolean(state, 1);
+        });
+        assert_eq!(
+            error.unwrap_err(),
+            "bad argument #2 to 'add' (integer expected, got boolean)"
+        );
+    }
+
+    #[test]
+    fn numbers_are_not_strings() {
+        let lua = Lua::new();
+
+        let error = lua.call("repeat", |state| unsafe {
+            integer(state, 1);
+            integer(state, 2);
+        });
+        assert_eq!(
+            error.unwrap_err(),
+            "bad argument #1 to 'repeat' (string expected, got number)"
+        );
+    }
+
+    #[test]
+    fn rejects_negative_sizes() {
+        let lua = Lua::new();
+
+        let error = lua.call("repeat", |state| unsafe {
+            string(state, "a");
+            integer(state, -1);
+        });
+        assert_eq!(
+            error.unwrap_err(),
+            "bad argument #2 to 'repeat' (non-negative integer expected, got number)"
+        );
+    }
+}
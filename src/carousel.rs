@@ -0,0 +1,149 @@
+use smash_arc::Hash40;
+
+use crate::manager::{AltManager, StageInfo};
+
+/// The stage select shows at most three previews
+pub const PREVIEW_COUNT: usize = 3;
+
+#[derive(Copy, Clone, Debug, Default)]
+struct PreviewSelection {
+    stage: Option<Hash40>,
+    form: usize,
+    selected: usize,
+}
+
+/// What a preview should show after its selection changed
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CarouselView {
+    /// The position of the selected alt in the stage's list, 0 is the base stage
+    pub selected: usize,
+
+    pub texture: Option<u32>,
+
+    /// The textures of the alts before and after the selected one, `None` hides them
+    pub left: Option<u32>,
+    pub right: Option<u32>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    Stay,
+    Next,
+    Prev,
+}
+
+pub struct Carousel {
+    previews: [PreviewSelection; PREVIEW_COUNT],
+}
+
+impl Carousel {
+    pub const fn new() -> Self {
+        const EMPTY: PreviewSelection = PreviewSelection {
+            stage: None,
+            form: 0,
+            selected: 0,
+        };
+
+        Self {
+            previews: [EMPTY; PREVIEW_COUNT],
+        }
+    }
+
+    /// Forgets the selection of a preview
+    pub fn reset(&mut self, preview: usize) {
+        if let Some(selection) = self.previews.get_mut(preview) {
+            *selection = PreviewSelection::default();
+        }
+    }
+}
+
+/// Moves the selection of a preview and returns what it should show. The selection goes back
+/// to the base stage whenever the preview switches to another stage or form
+pub fn update(
+    mgr: &mut AltManager,
+    preview: usize,
+    stage: Option<Hash40>,
+    form: usize,
+    step: Step,
+    favorites_only: bool,
+) -> CarouselView {
+    let Some(current) = mgr.carousel.previews.get(preview).copied() else {
+        log::warn!("Preview {preview} is out of range");
+        return CarouselView::default();
+    };
+
+    let mut selected = if current.stage == stage && current.form == form {
+        current.selected
+    } else {
+        0
+    };
+
+    let Some(stage) = stage else {
+        mgr.carousel.previews[preview] = PreviewSelection::default();
+        return CarouselView::default();
+    };
+
+    let info = StageInfo {
+        name: stage,
+        normal_form: form == 0,
+    };
+
+    let count = mgr.selectable_alt_count(info);
+    selected = selected.min(count);
+
+    let use_favorites = favorites_only && !mgr.favorite_indices(info).is_empty();
+
+    selected = match step {
+        Step::Stay => selected,
+        Step::Next | Step::Prev if use_favorites => {
+            mgr.next_favorite(info, selected, step == Step::Next)
+        }
+        Step::Next if selected == count => 0,
+        Step::Next => selected + 1,
+        Step::Prev if selected == 0 => count,
+        Step::Prev => selected - 1,
+    };
+
+    mgr.carousel.previews[preview] = PreviewSelection {
+        stage: Some(stage),
+        form,
+        selected,
+    };
+
+    let texture = |alt_id| mgr.preview_texture_index(stage, form, alt_id);
+
+    let (left, right) = match count {
+        0 => (None, None),
+        1 => (None, texture(if selected == 0 { 1 } else { 0 })),
+        _ => (
+            texture(if selected == 0 { count } else { selected - 1 }),
+            texture(if selected == count { 0 } else { selected + 1 }),
+        ),
+    };
+
+    CarouselView {
+        selected,
+        texture: texture(selected),
+        left,
+        right,
+    }
+}
+
+/// Sets the selection of a preview, for when the script changes it directly
+pub fn select(
+    mgr: &mut AltManager,
+    preview: usize,
+    stage: Option<Hash40>,
+    form: usize,
+    selected: usize,
+) -> CarouselView {
+    if let Some(current) = mgr.carousel.previews.get_mut(preview) {
+        *current = PreviewSelection {
+            stage,
+            form,
+            selected,
+        };
+    }
+
+    update(mgr, preview, stage, form, Step::Stay, false)
+}
@@ -0,0 +1,472 @@
+use std::collections::BTreeMap;
+
+use locks::RwLock;
+use serde::{Deserialize, Deserializer};
+use smash_arc::Hash40;
+
+const CONFIG_PATH: &str = "sd:/ultimate/stage-alts/config.toml";
+
+pub static CONFIG: RwLock<Config> = RwLock::new(Config::new());
+
+/// What to do with the song that was picked on the stage select screen
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MusicPolicy {
+    /// Never change the song
+    Off,
+
+    /// Replace songs that aren't in the BGM database or the allow list
+    ReplaceUnknown,
+
+    /// Always play a random song from the stage's playlist
+    AlwaysRandomize,
+}
+
+/// Replaces how the song is picked for a single stage
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct StageMusicOverride {
+    /// Use another playlist (`bgm_set_id`) instead of the stage's own
+    pub playlist: Option<String>,
+
+    /// Use another incidence column of the playlist
+    pub setting: Option<usize>,
+
+    /// Pick from these songs instead of a playlist
+    pub songs: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct MusicConfig {
+    pub policy: MusicPolicy,
+
+    /// Songs that should never be replaced under [`MusicPolicy::ReplaceUnknown`]
+    pub allowed_songs: Vec<String>,
+
+    /// The song that is played when a stage has no playlist to pick from
+    pub fallback_song: String,
+
+    /// How many of the most recently picked songs are avoided when picking a random song
+    pub history_length: usize,
+
+    /// Per stage overrides, keyed by the stage's place id (e.g. `battlefield`)
+    pub stage_overrides: BTreeMap<String, StageMusicOverride>,
+}
+
+impl MusicConfig {
+    pub const fn new() -> Self {
+        Self {
+            policy: MusicPolicy::ReplaceUnknown,
+            allowed_songs: Vec::new(),
+            fallback_song: String::new(),
+            history_length: 0,
+            stage_overrides: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for MusicConfig {
+    fn default() -> Self {
+        Self {
+            fallback_song: String::from("ui_bgm_crs2_02_senjyou"),
+            history_length: 3,
+            ..Self::new()
+        }
+    }
+}
+
+/// Templates for the paths of a stage's UI textures. `{name}` is replaced with the stage's
+/// folder name, `{alt}` with the two digit alt number and `{ext}` with `.bntx` for the base
+/// stage or `_sXX.bntx` for alts. Empty templates in a per stage override fall back to the
+/// templates of the stage's layout
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct UiPathTemplates {
+    /// `stage_0`, the small icon
+    pub icon: String,
+
+    /// `stage_1`, the stage select panel
+    pub panel: String,
+
+    /// `stage_2`, the preview of the normal form
+    pub normal: String,
+
+    /// `stage_4`, the preview of the battlefield form
+    pub battle: String,
+
+    /// `stage_3`, the preview of the omega form
+    pub end: String,
+}
+
+impl UiPathTemplates {
+    pub const fn new() -> Self {
+        Self {
+            icon: String::new(),
+            panel: String::new(),
+            normal: String::new(),
+            battle: String::new(),
+            end: String::new(),
+        }
+    }
+
+    fn from_strs(normal: &str, battle: &str, end: &str) -> Self {
+        Self {
+            normal: normal.to_string(),
+            battle: battle.to_string(),
+            end: end.to_string(),
+            ..Self::new()
+        }
+    }
+
+    fn with_icons(mut self, icon: &str, panel: &str) -> Self {
+        self.icon = icon.to_string();
+        self.panel = panel.to_string();
+        self
+    }
+
+    /// Replaces the templates that `other` sets, keeping the rest
+    fn merge(&mut self, other: Self) {
+        for (template, other) in [
+            (&mut self.icon, other.icon),
+            (&mut self.panel, other.panel),
+            (&mut self.normal, other.normal),
+            (&mut self.battle, other.battle),
+            (&mut self.end, other.end),
+        ] {
+            if !other.is_empty() {
+                *template = other;
+            }
+        }
+    }
+}
+
+/// Adds the user's DLC stages to the default ones
+fn merge_dlc_stages<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    let mut stages = UiConfig::default().dlc_stages;
+    for stage in Vec::<String>::deserialize(deserializer)? {
+        if !stages.contains(&stage) {
+            stages.push(stage);
+        }
+    }
+
+    Ok(stages)
+}
+
+/// Merges the user's per stage templates over the default ones
+fn merge_stage_templates<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, UiPathTemplates>, D::Error> {
+    let mut stages = UiConfig::default().stages;
+    for (stage, templates) in BTreeMap::<String, UiPathTemplates>::deserialize(deserializer)? {
+        stages.entry(stage).or_default().merge(templates);
+    }
+
+    Ok(stages)
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct UiConfig {
+    /// Stages whose UI lives in `ui/replace_patch` like the DLC stages, added to the defaults
+    #[serde(deserialize_with = "merge_dlc_stages")]
+    pub dlc_stages: Vec<String>,
+
+    /// The templates used for stages that aren't DLC stages and have no override
+    pub vanilla: UiPathTemplates,
+
+    /// The templates used for the stages in `dlc_stages`
+    pub dlc: UiPathTemplates,
+
+    /// Per stage templates, keyed by the stage's folder name. Templates set here replace the
+    /// default ones of the stage, the others are kept
+    #[serde(deserialize_with = "merge_stage_templates")]
+    pub stages: BTreeMap<String, UiPathTemplates>,
+
+    /// Generate textures showing the alt's number and name for alts that don't ship their own
+    /// UI. They are built when the game loads them and never written to the SD card
+    pub placeholder_textures: bool,
+}
+
+impl UiConfig {
+    pub const fn new() -> Self {
+        Self {
+            dlc_stages: Vec::new(),
+            vanilla: UiPathTemplates::new(),
+            dlc: UiPathTemplates::new(),
+            stages: BTreeMap::new(),
+            placeholder_textures: false,
+        }
+    }
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        let battlefield = |normal: &str| {
+            UiPathTemplates::from_strs(
+                normal,
+                "ui/replace/stage/stage_4/stage_4_battlefield{ext}",
+                "ui/replace/stage/stage_3/stage_3_battlefield_s{alt}.bntx",
+            )
+        };
+
+        let training = "ui/replace/stage/stage_2/stage_2_{name}{ext}";
+
+        Self {
+            dlc_stages: [
+                "jack_mementoes",
+                "brave_altar",
+                "buddy_spiral",
+                "dolly_stadium",
+                "fe_shrine",
+                "tantan_spring",
+                "pickel_world",
+                "ff_cave",
+                "xeno_alst",
+                "demon_dojo",
+                "trail_castle",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+            vanilla: UiPathTemplates::from_strs(
+                "ui/replace/stage/stage_2/stage_2_{name}{ext}",
+                "ui/replace/stage/stage_4/stage_4_{name}{ext}",
+                "ui/replace/stage/stage_3/stage_3_{name}{ext}",
+            )
+            .with_icons(
+                "ui/replace/stage/stage_0/stage_0_{name}{ext}",
+                "ui/replace/stage/stage_1/stage_1_{name}{ext}",
+            ),
+            dlc: UiPathTemplates::from_strs(
+                "ui/replace_patch/stage/stage_2/stage_2_{name}{ext}",
+                "ui/replace_patch/stage/stage_4/stage_4_{name}{ext}",
+                "ui/replace_patch/stage/stage_3/stage_3_{name}{ext}",
+            )
+            .with_icons(
+                "ui/replace_patch/stage/stage_0/stage_0_{name}{ext}",
+                "ui/replace_patch/stage/stage_1/stage_1_{name}{ext}",
+            ),
+            stages: BTreeMap::from([
+                (
+                    String::from("training"),
+                    UiPathTemplates::from_strs(training, training, training),
+                ),
+                (
+                    String::from("battlefield"),
+                    battlefield("ui/replace/stage/stage_2/stage_2_battlefield{ext}"),
+                ),
+                (
+                    String::from("battlefield_l"),
+                    battlefield("ui/replace/stage/stage_2/stage_2_battlefieldl{ext}").with_icons(
+                        "ui/replace/stage/stage_0/stage_0_battlefieldl{ext}",
+                        "ui/replace/stage/stage_1/stage_1_battlefieldl{ext}",
+                    ),
+                ),
+                (
+                    String::from("battlefield_s"),
+                    battlefield("ui/replace_patch/stage/stage_2/stage_2_battlefields{ext}")
+                        .with_icons(
+                            "ui/replace_patch/stage/stage_0/stage_0_battlefields{ext}",
+                            "ui/replace_patch/stage/stage_1/stage_1_battlefields{ext}",
+                        ),
+                ),
+                (
+                    String::from("end"),
+                    UiPathTemplates::from_strs(
+                        "ui/replace/stage/stage_2/stage_2_end{ext}",
+                        "ui/replace/stage/stage_4/stage_4_end{ext}",
+                        "ui/replace/stage/stage_2/stage_2_end{ext}",
+                    ),
+                ),
+            ]),
+            placeholder_textures: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ConflictConfig {
+    /// The folder arcropolis loads mods from
+    pub mods_path: String,
+
+    /// Move alt folders that claim an already used slot to a free slot. Only the slot the game
+    /// sees changes, the mods' folders are left as they are
+    pub remap: bool,
+}
+
+impl ConflictConfig {
+    pub const fn new() -> Self {
+        Self {
+            mods_path: String::new(),
+            remap: false,
+        }
+    }
+}
+
+impl Default for ConflictConfig {
+    fn default() -> Self {
+        Self {
+            mods_path: String::from("sd:/ultimate/mods"),
+            ..Self::new()
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct StageSelectConfig {
+    /// Cycling through alts only stops at the favorites of stages that have any. Scripts can
+    /// still ask for either cycle themselves
+    pub favorites_only: bool,
+
+    /// The button that toggles the favorite on the HDR stage select, named like the game's
+    /// `LIB_BUTTON_*` inputs (e.g. `zl`, `x`, `minus`). L, R and ZR already cycle alts and
+    /// strike stages there, and an empty or unknown name leaves the toggle unbound
+    pub favorite_button: String,
+}
+
+impl StageSelectConfig {
+    pub const fn new() -> Self {
+        Self {
+            favorites_only: false,
+            favorite_button: String::new(),
+        }
+    }
+}
+
+impl Default for StageSelectConfig {
+    fn default() -> Self {
+        Self {
+            favorite_button: String::from("zl"),
+            ..Self::new()
+        }
+    }
+}
+
+/// Controls which alts of a stage can be picked and how they are listed, alts are referred
+/// to by their slot
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct StagePolicy {
+    /// The alt that is played when the player leaves the stage on its first entry
+    pub default: Option<usize>,
+
+    /// Always play this alt, no matter what was selected
+    pub lock: Option<usize>,
+
+    /// Alts that stay installed but can't be selected
+    pub hidden: Vec<usize>,
+
+    /// Alts listed first, in this order. Alts that aren't listed follow in slot order
+    pub order: Vec<usize>,
+
+    /// Tags given to alts in addition to the ones from their manifest, keyed by tag
+    pub tags: BTreeMap<String, Vec<usize>>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Config {
+    pub music: MusicConfig,
+    pub ui: UiConfig,
+    pub conflicts: ConflictConfig,
+    pub stage_select: StageSelectConfig,
+
+    /// Per stage policies, keyed by the stage's folder name
+    pub stages: BTreeMap<String, StagePolicy>,
+}
+
+impl Config {
+    pub const fn new() -> Self {
+        Self {
+            music: MusicConfig::new(),
+            ui: UiConfig::new(),
+            conflicts: ConflictConfig::new(),
+            stage_select: StageSelectConfig::new(),
+            stages: BTreeMap::new(),
+        }
+    }
+
+    pub fn stage_policy(&self, stage: Hash40) -> Option<&StagePolicy> {
+        self.stages
+            .iter()
+            .find_map(|(name, policy)| (Hash40::from(name.as_str()) == stage).then_some(policy))
+    }
+}
+
+/// Loads the config from the SD card, falling back to the defaults if it is missing or invalid
+pub fn load() {
+    let config = match std::fs::read_to_string(CONFIG_PATH) {
+        Ok(data) => match toml::from_str(&data) {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("Failed to parse config, using the defaults: {e}");
+                Config::default()
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            log::info!("No config found, using the defaults");
+            Config::default()
+        }
+        Err(e) => {
+            log::error!("Failed to read config, using the defaults: {e:?}");
+            Config::default()
+        }
+    };
+
+    *CONFIG.write() = config;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_ui_entries_merge_over_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            [ui]
+            dlc_stages = ["my_stage"]
+
+            [ui.stages.battlefield]
+            icon = "ui/replace/stage/stage_0/stage_0_bf{ext}"
+
+            [ui.stages.my_stage]
+            normal = "ui/replace/stage/stage_2/stage_2_mine{ext}"
+            "#,
+        )
+        .unwrap();
+
+        let defaults = UiConfig::default();
+
+        assert!(config.ui.dlc_stages.starts_with(&defaults.dlc_stages));
+        assert!(config.ui.dlc_stages.contains(&String::from("my_stage")));
+
+        let battlefield = &config.ui.stages["battlefield"];
+        assert_eq!(battlefield.icon, "ui/replace/stage/stage_0/stage_0_bf{ext}");
+        assert_eq!(battlefield.battle, defaults.stages["battlefield"].battle);
+
+        assert!(config.ui.stages.contains_key("training"));
+        assert_eq!(
+            config.ui.stages["my_stage"].normal,
+            "ui/replace/stage/stage_2/stage_2_mine{ext}"
+        );
+    }
+
+    #[test]
+    fn favorites_only_cycle_is_opt_in() {
+        assert!(!Config::default().stage_select.favorites_only);
+
+        let config: Config = toml::from_str(
+            r#"
+            [stage_select]
+            favorites_only = true
+            "#,
+        )
+        .unwrap();
+        assert!(config.stage_select.favorites_only);
+        assert_eq!(config.stage_select.favorite_button, "zl");
+    }
+}
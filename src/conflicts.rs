@@ -0,0 +1,517 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use locks::RwLock;
+use smash_arc::Hash40;
+
+use crate::{config, folders, manager::StageInfo};
+
+/// A folder that provides an alt, and the mod it is in
+#[derive(Debug)]
+struct AltSource {
+    mod_name: String,
+    folder: String,
+}
+
+/// Every alt folder of the enabled mods, keyed by stage folder name, whether it is the normal
+/// form and slot
+type AltSources = BTreeMap<(String, bool, usize), Vec<AltSource>>;
+
+/// How an alt folder that claims an already used slot is moved to a free one
+#[derive(Debug, PartialEq, Eq)]
+enum Remap {
+    /// A folder with a name of its own moves as a whole
+    Folder {
+        stage: String,
+        normal_form: bool,
+        folder: String,
+        slot: usize,
+    },
+
+    /// A mod's copy of a folder that another mod also ships. Arcropolis merges both into one
+    /// folder, so the copy keeps that folder and its files are served for its new slot
+    Copy {
+        stage: String,
+        normal_form: bool,
+        folder: String,
+        mod_name: String,
+        slot: usize,
+    },
+}
+
+/// A file of an alt folder that more than one mod ships
+struct CopiedFile {
+    info: StageInfo,
+
+    /// The file for each slot the folder provides, the first is the folder's own slot
+    copies: Vec<(usize, PathBuf)>,
+}
+
+/// The files of every copied alt folder, keyed by the hash of their path
+static COPIED_FILES: RwLock<BTreeMap<u64, CopiedFile>> = RwLock::new(BTreeMap::new());
+
+/// The folder of the mod each copied slot comes from
+static COPY_FOLDERS: RwLock<BTreeMap<(StageInfo, usize), PathBuf>> = RwLock::new(BTreeMap::new());
+
+/// The alt each stage form is being loaded with, this picks which copy a file is read from
+static LOADING_ALTS: RwLock<BTreeMap<StageInfo, usize>> = RwLock::new(BTreeMap::new());
+
+/// The names of the folders in a folder, empty if it can't be read
+pub fn read_dir_names(path: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return vec![];
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map(|ty| ty.is_dir()).unwrap_or(false))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect()
+}
+
+/// The paths of every file in a folder and its subfolders, relative to it
+fn relative_file_paths(folder: &Path, prefix: &str, files: &mut BTreeSet<String>) {
+    let Ok(entries) = std::fs::read_dir(folder) else {
+        return;
+    };
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+
+        let path = format!("{prefix}{name}");
+        if entry.file_type().map(|ty| ty.is_dir()).unwrap_or(false) {
+            relative_file_paths(&entry.path(), &format!("{path}/"), files);
+        } else {
+            files.insert(path);
+        }
+    }
+}
+
+/// Whether arcropolis loads a mod, this follows its mod manager and presets instead of only
+/// the folder names
+fn is_mod_enabled(mod_path: &Path) -> bool {
+    let path = mod_path.to_string_lossy();
+    arcropolis_api::is_mod_enabled(Hash40::from(path.as_ref()).0)
+}
+
+/// The name and folder of every mod that arcropolis loads
+pub fn enabled_mods() -> Vec<(String, PathBuf)> {
+    let mods_path = PathBuf::from(&config::CONFIG.read().conflicts.mods_path);
+
+    read_dir_names(&mods_path)
+        .into_iter()
+        .map(|name| {
+            let path = mods_path.join(&name);
+            (name, path)
+        })
+        .filter(|(_, path)| is_mod_enabled(path))
+        .collect()
+}
+
+fn scan_mods(mods: &[(String, PathBuf)]) -> AltSources {
+    let mut sources = AltSources::new();
+
+    for (mod_name, mod_path) in mods {
+        for stage in read_dir_names(&mod_path.join("stage")) {
+            let stage_hash = Hash40::from(stage.as_str());
+
+            for folder in read_dir_names(&mod_path.join("stage").join(&stage)) {
+                let Some((slot, normal_form)) =
+                    folders::lookup(stage_hash, Hash40::from(folder.as_str()))
+                else {
+                    continue;
+                };
+
+                sources
+                    .entry((stage.clone(), normal_form, slot))
+                    .or_default()
+                    .push(AltSource {
+                        mod_name: mod_name.clone(),
+                        folder,
+                    });
+            }
+        }
+    }
+
+    sources
+}
+
+fn form_name(normal_form: bool) -> &'static str {
+    if normal_form {
+        "normal"
+    } else {
+        "battle"
+    }
+}
+
+/// The first free slot after `slot`, wrapping around to the low slots
+fn next_free_slot(used: &BTreeSet<usize>, slot: usize) -> Option<usize> {
+    (slot + 1..=folders::MAX_NUMBERED_SLOT)
+        .chain(1..slot)
+        .find(|slot| !used.contains(slot))
+}
+
+/// Decides which alt folders move to which slot. For every slot that more than one folder
+/// claims, the folder the patching layer already uses for it keeps it and, if `remap` is set,
+/// the others move to the next free slot. The same goes for every mod after the first that
+/// ships the same folder
+fn plan_remaps(sources: &AltSources, remap: bool) -> Vec<Remap> {
+    let mut used_slots: BTreeMap<(&str, bool), BTreeSet<usize>> = BTreeMap::new();
+    for (stage, normal_form, slot) in sources.keys() {
+        used_slots
+            .entry((stage.as_str(), *normal_form))
+            .or_default()
+            .insert(*slot);
+    }
+
+    let mut remaps = vec![];
+    let mut conflicts = 0;
+
+    for ((stage, normal_form, slot), alts) in sources.iter().filter(|(_, alts)| alts.len() > 1) {
+        conflicts += 1;
+
+        let form = form_name(*normal_form);
+
+        let mut by_folder: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for alt in alts {
+            by_folder
+                .entry(alt.folder.as_str())
+                .or_default()
+                .push(alt.mod_name.as_str());
+        }
+
+        // The folder the patching layer already uses for the slot keeps it
+        let info = StageInfo {
+            name: Hash40::from(stage.as_str()),
+            normal_form: *normal_form,
+        };
+        let current = folders::folder_name(info, *slot);
+        let keep = by_folder
+            .keys()
+            .find(|folder| Hash40::from(**folder) == current)
+            .or_else(|| by_folder.keys().next())
+            .copied()
+            .unwrap_or_default();
+
+        let names = by_folder.keys().copied().collect::<Vec<_>>();
+        if names.len() > 1 {
+            log::warn!(
+                "Alt {slot} of the {form} form of {stage} is provided by more than one folder: {}",
+                names.join(", ")
+            );
+        }
+
+        for (folder, mods) in by_folder.iter().filter(|(_, mods)| mods.len() > 1) {
+            log::warn!(
+                "{stage}/{folder} is shipped by more than one mod: {}",
+                mods.join(", ")
+            );
+        }
+
+        if !remap {
+            log::warn!(
+                "Only {stage}/{keep} from {} is used, enable conflicts.remap to use the others",
+                by_folder[keep][0]
+            );
+            continue;
+        }
+
+        let used = used_slots
+            .entry((stage.as_str(), *normal_form))
+            .or_default();
+
+        for (folder, mods) in by_folder.iter() {
+            if *folder != keep {
+                let Some(new_slot) = next_free_slot(used, *slot) else {
+                    log::error!("No free slot left for {stage}/{folder}");
+                    continue;
+                };
+                used.insert(new_slot);
+
+                log::warn!(
+                    "Remapping {stage}/{folder} to slot {new_slot}, its panel shows the base or placeholder UI textures"
+                );
+
+                remaps.push(Remap::Folder {
+                    stage: stage.clone(),
+                    normal_form: *normal_form,
+                    folder: String::from(*folder),
+                    slot: new_slot,
+                });
+            }
+
+            // The first mod's copy stays with the folder, wherever the folder ends up
+            for mod_name in mods.iter().skip(1) {
+                let Some(new_slot) = next_free_slot(used, *slot) else {
+                    log::error!("No free slot left for {stage}/{folder} of {mod_name}");
+                    continue;
+                };
+                used.insert(new_slot);
+
+                log::warn!("Remapping {stage}/{folder} of {mod_name} to slot {new_slot}");
+
+                remaps.push(Remap::Copy {
+                    stage: stage.clone(),
+                    normal_form: *normal_form,
+                    folder: String::from(*folder),
+                    mod_name: String::from(*mod_name),
+                    slot: new_slot,
+                });
+            }
+        }
+    }
+
+    if conflicts != 0 {
+        log::warn!("Found {conflicts} alt slot conflicts between mods");
+    }
+
+    remaps
+}
+
+extern "C" fn load_copied_file(
+    hash: u64,
+    buffer: *mut u8,
+    buf_len: usize,
+    out_size: &mut usize,
+) -> bool {
+    let path = {
+        let files = COPIED_FILES.read();
+        let Some(file) = files.get(&hash) else {
+            return false;
+        };
+
+        let slot = LOADING_ALTS
+            .read()
+            .get(&file.info)
+            .copied()
+            .unwrap_or_default();
+
+        file.copies
+            .iter()
+            .find(|(copy_slot, _)| *copy_slot == slot)
+            .or_else(|| file.copies.first())
+            .map(|(_, path)| path.clone())
+    };
+
+    let Some(path) = path else {
+        return false;
+    };
+
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(e) => {
+            log::error!("Failed to read {}: {e:?}", path.display());
+            return false;
+        }
+    };
+
+    if data.len() > buf_len {
+        log::error!(
+            "{} is {:#x} bytes but the buffer is only {buf_len:#x}",
+            path.display(),
+            data.len()
+        );
+        return false;
+    }
+
+    let buffer = unsafe { std::slice::from_raw_parts_mut(buffer, buf_len) };
+    buffer[..data.len()].copy_from_slice(&data);
+    *out_size = data.len();
+
+    true
+}
+
+/// Serves the files of a folder that more than one mod ships from the mod of the slot it is
+/// loaded for. Files a copy doesn't have are read from the first mod that ships the folder
+fn register_copies(
+    info: StageInfo,
+    stage: &str,
+    folder: &str,
+    slot: usize,
+    copies: &[(usize, &Path)],
+    mods: &[(String, PathBuf)],
+) {
+    let folder_path = |mod_path: &Path| mod_path.join("stage").join(stage).join(folder);
+
+    // Whichever mod comes first provides the folder's own slot, like it does when merged
+    let Some(owner) = mods
+        .iter()
+        .map(|(_, mod_path)| folder_path(mod_path))
+        .find(|path| path.exists())
+    else {
+        return;
+    };
+
+    let mut relative = BTreeSet::new();
+    relative_file_paths(&owner, "", &mut relative);
+    for (copy_slot, mod_path) in copies {
+        relative_file_paths(&folder_path(mod_path), "", &mut relative);
+        COPY_FOLDERS
+            .write()
+            .insert((info, *copy_slot), folder_path(mod_path));
+    }
+
+    let mut files = COPIED_FILES.write();
+    for path in relative {
+        let owner_file = owner.join(&path);
+
+        let mut file_copies = vec![(slot, owner_file.clone())];
+        for (copy_slot, mod_path) in copies {
+            let copy_file = folder_path(mod_path).join(&path);
+            if copy_file.exists() {
+                file_copies.push((*copy_slot, copy_file));
+            } else if owner_file.exists() {
+                file_copies.push((*copy_slot, owner_file.clone()));
+            }
+        }
+
+        file_copies.retain(|(_, file)| file.exists());
+
+        let size = file_copies
+            .iter()
+            .filter_map(|(_, file)| std::fs::metadata(file).ok())
+            .map(|metadata| metadata.len() as usize)
+            .max()
+            .unwrap_or_default();
+
+        let arc_path = format!("stage/{stage}/{folder}/{path}");
+        files.insert(
+            Hash40::from(arc_path.as_str()).0,
+            CopiedFile {
+                info,
+                copies: file_copies,
+            },
+        );
+
+        arcropolis_api::register_callback(arc_path.as_str(), size, load_copied_file);
+    }
+}
+
+/// The folder in its own mod of a slot that was moved off a folder more than one mod ships
+pub fn copy_folder(info: StageInfo, slot: usize) -> Option<PathBuf> {
+    COPY_FOLDERS.read().get(&(info, slot)).cloned()
+}
+
+/// Records the alt a stage form is loaded with, so that the files of copied folders are read
+/// from the right mod
+pub fn set_loading_alt(info: StageInfo, slot: usize) {
+    if COPIED_FILES.read().is_empty() {
+        return;
+    }
+
+    LOADING_ALTS.write().insert(info, slot);
+}
+
+/// Looks for alt folders of the enabled mods that provide the same slot of a stage form.
+///
+/// If enabled, all but one of them are remapped to free slots. Different folders that claim the
+/// same slot (a named folder from `alts.toml` and a numbered one) go through the folder table
+/// that discovering and patching alts use. Mods that ship the same folder are merged into one
+/// folder by arcropolis, so each extra copy gets a slot in the folder table that points at the
+/// merged folder, and its files are served from its mod when that slot loads. Nothing is moved
+/// on the SD card. This has to run before the alts are discovered
+pub fn check() {
+    let should_remap = config::CONFIG.read().conflicts.remap;
+
+    let mods = enabled_mods();
+    let sources = scan_mods(&mods);
+
+    let mut copies: BTreeMap<(String, bool, String), Vec<(usize, &Path)>> = BTreeMap::new();
+
+    for remap in plan_remaps(&sources, should_remap) {
+        match remap {
+            Remap::Folder {
+                stage,
+                normal_form,
+                folder,
+                slot,
+            } => {
+                let info = StageInfo {
+                    name: Hash40::from(stage.as_str()),
+                    normal_form,
+                };
+                folders::remap(info, Hash40::from(folder.as_str()), slot);
+            }
+            Remap::Copy {
+                stage,
+                normal_form,
+                folder,
+                mod_name,
+                slot,
+            } => {
+                let Some((_, mod_path)) = mods.iter().find(|(name, _)| *name == mod_name) else {
+                    continue;
+                };
+
+                copies
+                    .entry((stage, normal_form, folder))
+                    .or_default()
+                    .push((slot, mod_path.as_path()));
+            }
+        }
+    }
+
+    for ((stage, normal_form, folder), copies) in copies.iter() {
+        let info = StageInfo {
+            name: Hash40::from(stage.as_str()),
+            normal_form: *normal_form,
+        };
+        let folder_hash = Hash40::from(folder.as_str());
+
+        let Some((slot, _)) = folders::lookup(info.name, folder_hash) else {
+            continue;
+        };
+
+        for (copy_slot, _) in copies.iter() {
+            folders::add_copy(info, folder_hash, *copy_slot);
+        }
+
+        register_copies(info, stage, folder, slot, copies, &mods);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(mod_name: &str, folder: &str) -> AltSource {
+        AltSource {
+            mod_name: String::from(mod_name),
+            folder: String::from(folder),
+        }
+    }
+
+    #[test]
+    fn same_folder_in_two_mods_gets_the_next_free_slot() {
+        let sources = AltSources::from([
+            (
+                (String::from("battlefield"), true, 5),
+                vec![
+                    source("Cool Battlefield", "normal_s05"),
+                    source("Other Battlefield", "normal_s05"),
+                ],
+            ),
+            (
+                (String::from("battlefield"), true, 6),
+                vec![source("Third Battlefield", "normal_s06")],
+            ),
+        ]);
+
+        assert_eq!(
+            plan_remaps(&sources, true),
+            vec![Remap::Copy {
+                stage: String::from("battlefield"),
+                normal_form: true,
+                folder: String::from("normal_s05"),
+                mod_name: String::from("Other Battlefield"),
+                slot: 7,
+            }]
+        );
+
+        assert!(plan_remaps(&sources, false).is_empty());
+    }
+}
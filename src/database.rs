@@ -0,0 +1,367 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+use hash40::{hash40, Hash40};
+use prc::{ParamKind, ParamList, ParamStruct};
+use smash_arc::{ArcLookup, Region};
+
+use crate::resources::{
+    self,
+    types::{FilesystemInfo, LoadState, ResServiceNX},
+};
+
+pub const STAGE_DB_PATH: &str = "ui/param/database/ui_stage_db.prc";
+pub const BGM_DB_PATH: &str = "ui/param/database/ui_bgm_db.prc";
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    /// The param file itself could not be read
+    Read(String),
+
+    /// A required top level key is missing
+    MissingKey(Hash40),
+
+    /// A top level key exists but isn't the type we expected
+    UnexpectedType(Hash40),
+}
+
+impl Display for DatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Read(e) => write!(f, "failed to read param data: {e}"),
+            Self::MissingKey(key) => write!(f, "missing key {:#x}", key.0),
+            Self::UnexpectedType(key) => write!(f, "key {:#x} has an unexpected type", key.0),
+        }
+    }
+}
+
+/// Asks the resource service to load the databases and holds a reference to them so they
+/// stay resident. They go through the regular loading path, so arcropolis applies every mod's
+/// prc and prcx patches to them before we read them
+pub fn request_database_files() {
+    let fs = FilesystemInfo::instance().unwrap();
+    let res_service = ResServiceNX::instance().unwrap();
+
+    for path in [STAGE_DB_PATH, BGM_DB_PATH] {
+        let Ok(index) = fs
+            .arc()
+            .get_file_path_index_from_hash(smash_arc::Hash40::from(path))
+        else {
+            log::error!("Failed to get file path index for {path}");
+            continue;
+        };
+
+        unsafe {
+            resources::increment_ref_count(fs, index.0);
+            resources::add_to_resource_list(res_service, index.0, 0);
+        }
+    }
+}
+
+/// Copies a file out of the game's loaded data, returning `None` if it hasn't finished loading
+pub fn read_loaded_file(path: &str) -> Option<Vec<u8>> {
+    let fs = FilesystemInfo::instance()?;
+    let arc = fs.arc();
+    let hash = smash_arc::Hash40::from(path);
+
+    let index = arc.get_file_path_index_from_hash(hash).ok()?;
+    let loaded_path = fs.get_loaded_filepaths().get(index.0 as usize)?;
+    if loaded_path.is_loaded == 0 {
+        return None;
+    }
+
+    let loaded_data = &fs.get_loaded_datas()[loaded_path];
+    if loaded_data.state != LoadState::Loaded || loaded_data.data.is_null() {
+        return None;
+    }
+
+    let info = arc.get_file_info_from_hash(hash).ok()?;
+    let size = arc.get_file_data(info, Region::None).decomp_size as usize;
+
+    Some(unsafe { std::slice::from_raw_parts(loaded_data.data, size) }.to_vec())
+}
+
+pub fn read_param_data(data: &[u8]) -> Result<ParamStruct, DatabaseError> {
+    prc::read_stream(&mut std::io::Cursor::new(data))
+        .map_err(|e| DatabaseError::Read(format!("{e:?}")))
+}
+
+pub fn prc_get(prc: &ParamStruct, key: Hash40) -> Option<&ParamKind> {
+    prc.0.iter().find_map(|(k, v)| (*k == key).then_some(v))
+}
+
+pub fn prc_get_hash(prc: &ParamStruct, key: Hash40) -> Option<Hash40> {
+    match prc_get(prc, key)? {
+        ParamKind::Hash(hash) => Some(*hash),
+        _ => None,
+    }
+}
+
+pub fn prc_get_str(prc: &ParamStruct, key: Hash40) -> Option<&str> {
+    match prc_get(prc, key)? {
+        ParamKind::Str(value) => Some(value.as_str()),
+        _ => None,
+    }
+}
+
+pub fn prc_get_int(prc: &ParamStruct, key: Hash40) -> Option<i64> {
+    let value = match prc_get(prc, key)? {
+        ParamKind::I8(value) => *value as i64,
+        ParamKind::U8(value) => *value as i64,
+        ParamKind::I16(value) => *value as i64,
+        ParamKind::U16(value) => *value as i64,
+        ParamKind::I32(value) => *value as i64,
+        ParamKind::U32(value) => *value as i64,
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+pub fn prc_get_list(prc: &ParamStruct, key: Hash40) -> Result<&ParamList, DatabaseError> {
+    match prc_get(prc, key) {
+        Some(ParamKind::List(list)) => Ok(list),
+        Some(_) => Err(DatabaseError::UnexpectedType(key)),
+        None => Err(DatabaseError::MissingKey(key)),
+    }
+}
+
+/// Reads the place of a stage id from the game's tables, the way the game does when a match
+/// loads. Entries of the stage table hold the index of their place in the place table, ids past
+/// the end of the stage table read garbage, so callers check the place against ui_stage_db
+pub unsafe fn game_stage_place(stage_id: usize) -> Hash40 {
+    let text = skyline::hooks::getRegionAddress(skyline::hooks::Region::Text) as *const u8;
+
+    let stage_entry = text.add(0x45489b8).add(stage_id * 0x48);
+    let place_id = *(stage_entry.add(0x3c) as *const u32) as usize;
+
+    let place_entry = text.add(0x4547420).add(place_id * 0x28) as *const u64;
+    Hash40(*place_entry)
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct StageDbEntry {
+    pub ui_stage_id: Hash40,
+    pub stage_place_id: Hash40,
+    pub bgm_set_id: Option<Hash40>,
+
+    /// The stage's name in the message labels, `nam_stage_name_<name_id>`
+    pub name_id: Option<String>,
+
+    /// Which of the playlist's incidence columns this stage uses
+    pub bgm_setting_no: usize,
+}
+
+/// Parses the stage entries of `ui_stage_db.prc`, entries that are missing the fields we
+/// need are skipped instead of failing the whole database
+pub fn parse_stage_db(data: &[u8]) -> Result<Vec<StageDbEntry>, DatabaseError> {
+    let prc = read_param_data(data)?;
+    let list = prc_get_list(&prc, hash40("db_root"))?;
+
+    let mut entries = Vec::with_capacity(list.0.len());
+
+    for (index, param) in list.0.iter().enumerate() {
+        let ParamKind::Struct(stage) = param else {
+            log::warn!("Skipping ui_stage_db entry {index} because it is not a struct");
+            continue;
+        };
+
+        let (Some(ui_stage_id), Some(stage_place_id)) = (
+            prc_get_hash(stage, hash40("ui_stage_id")),
+            prc_get_hash(stage, hash40("stage_place_id")),
+        ) else {
+            log::warn!("Skipping ui_stage_db entry {index} because it has no ui_stage_id or stage_place_id");
+            continue;
+        };
+
+        entries.push(StageDbEntry {
+            ui_stage_id,
+            stage_place_id,
+            bgm_set_id: prc_get_hash(stage, hash40("bgm_set_id")),
+            name_id: prc_get_str(stage, hash40("name_id")).map(String::from),
+            bgm_setting_no: prc_get_int(stage, hash40("bgm_setting_no"))
+                .map(|value| value.clamp(0, PLAYLIST_COLUMNS as i64 - 1) as usize)
+                .unwrap_or_default(),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Playlists have a column of incidence values for each stage that uses them
+pub const PLAYLIST_COLUMNS: usize = 16;
+
+#[derive(Copy, Clone, Debug)]
+pub struct PlaylistEntry {
+    pub song: Hash40,
+
+    /// How likely the song is to be picked for each column, 0 means it never gets picked
+    pub incidence: [u32; PLAYLIST_COLUMNS],
+}
+
+pub struct BgmDatabase {
+    /// Every song registered in `db_root`, including the ones added by music mods
+    pub registered: HashSet<Hash40>,
+
+    /// The songs of every playlist, keyed by its `bgm_set_id`
+    pub playlists: HashMap<Hash40, Vec<PlaylistEntry>>,
+}
+
+fn collect_playlist_entries(list: &ParamList, name: Hash40) -> Option<Vec<PlaylistEntry>> {
+    let mut songs = Vec::with_capacity(list.0.len());
+    let mut has_songs = false;
+
+    for (index, param) in list.0.iter().enumerate() {
+        let ParamKind::Struct(param) = param else {
+            continue;
+        };
+
+        match prc_get(param, hash40("ui_bgm_id")) {
+            Some(ParamKind::Hash(hash)) => {
+                has_songs = true;
+
+                // Entries without incidence values (like the ones in db_root) are always
+                // eligible so that they behave like an unweighted list
+                let mut incidence = [1; PLAYLIST_COLUMNS];
+                for (column, value) in incidence.iter_mut().enumerate() {
+                    if let Some(raw) = prc_get_int(param, hash40(&format!("incidence{column}"))) {
+                        *value = raw.max(0) as u32;
+                    }
+                }
+
+                songs.push(PlaylistEntry {
+                    song: *hash,
+                    incidence,
+                });
+            }
+            Some(_) => {
+                log::warn!(
+                    "Skipping entry {index} of {:#x} because its ui_bgm_id is not a hash",
+                    name.0
+                );
+            }
+            None => {}
+        }
+    }
+
+    has_songs.then_some(songs)
+}
+
+/// Parses `ui_bgm_db.prc`. Every top level list other than `db_root` whose entries reference
+/// a `ui_bgm_id` is a playlist
+pub fn parse_bgm_db(data: &[u8]) -> Result<BgmDatabase, DatabaseError> {
+    let prc = read_param_data(data)?;
+
+    let db_root = prc_get_list(&prc, hash40("db_root"))?;
+    let registered = collect_playlist_entries(db_root, hash40("db_root"))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|entry| entry.song)
+        .collect();
+
+    let mut playlists = HashMap::new();
+
+    for (key, value) in prc.0.iter() {
+        if *key == hash40("db_root") {
+            continue;
+        }
+
+        let ParamKind::List(list) = value else {
+            continue;
+        };
+
+        if let Some(songs) = collect_playlist_entries(list, *key) {
+            playlists.insert(*key, songs);
+        }
+    }
+
+    Ok(BgmDatabase {
+        registered,
+        playlists,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stage_row(
+        ui_stage_id: &str,
+        name_id: &str,
+        stage_place_id: &str,
+        bgm_set_id: &str,
+    ) -> ParamKind {
+        ParamKind::Struct(ParamStruct(
+            [
+                (hash40("ui_stage_id"), ParamKind::Hash(hash40(ui_stage_id))),
+                (hash40("name_id"), ParamKind::Str(String::from(name_id))),
+                (hash40("save_no"), ParamKind::I8(1)),
+                (
+                    hash40("ui_series_id"),
+                    ParamKind::Hash(hash40("ui_series_none")),
+                ),
+                (
+                    hash40("stage_place_id"),
+                    ParamKind::Hash(hash40(stage_place_id)),
+                ),
+                (hash40("secret_stage_place_id"), ParamKind::Hash(hash40(""))),
+                (hash40("can_select"), ParamKind::Bool(true)),
+                (hash40("disp_order"), ParamKind::I8(0)),
+                (hash40("bgm_set_id"), ParamKind::Hash(hash40(bgm_set_id))),
+                (hash40("bgm_setting_no"), ParamKind::U8(2)),
+                (hash40("is_dlc"), ParamKind::Bool(false)),
+            ]
+            .into_iter()
+            .collect(),
+        ))
+    }
+
+    fn stage_db(rows: Vec<ParamKind>) -> Vec<u8> {
+        let root = ParamStruct(
+            [(hash40("db_root"), ParamKind::List(ParamList(rows)))]
+                .into_iter()
+                .collect(),
+        );
+
+        let mut data = std::io::Cursor::new(vec![]);
+        prc::write_stream(&mut data, &root).unwrap();
+        data.into_inner()
+    }
+
+    #[test]
+    fn stage_db_reads_places_of_real_rows() {
+        let data = stage_db(vec![
+            stage_row(
+                "ui_stage_battle_field",
+                "battle_field",
+                "battlefield",
+                "ui_bgm_set_battle_field",
+            ),
+            stage_row("ui_stage_end", "end", "end", "ui_bgm_set_end"),
+            // The random panel has no place
+            ParamKind::Struct(ParamStruct(
+                [(
+                    hash40("ui_stage_id"),
+                    ParamKind::Hash(hash40("ui_stage_random")),
+                )]
+                .into_iter()
+                .collect(),
+            )),
+        ]);
+
+        let entries = parse_stage_db(&data).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0].ui_stage_id, hash40("ui_stage_battle_field"));
+        assert_eq!(entries[0].stage_place_id, hash40("battlefield"));
+        assert_eq!(
+            entries[0].bgm_set_id,
+            Some(hash40("ui_bgm_set_battle_field"))
+        );
+        assert_eq!(entries[0].bgm_setting_no, 2);
+        assert_eq!(entries[0].name_id.as_deref(), Some("battle_field"));
+        assert_eq!(entries[1].stage_place_id, hash40("end"));
+    }
+}
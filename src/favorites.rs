@@ -0,0 +1,116 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use smash_arc::Hash40;
+
+use crate::{
+    manager::StageInfo,
+    storage::{PendingWrite, Stored, TextTable},
+};
+
+const FAVORITES_PATH: &str = "sd:/ultimate/stage-alts/favorites.txt";
+
+/// The favorite alt slots of every stage form
+pub struct FavoriteTable(BTreeMap<StageInfo, BTreeSet<usize>>);
+
+/// The favorites, saved to the SD card after they change
+pub struct Favorites {
+    entries: Stored<FavoriteTable>,
+}
+
+impl TextTable for FavoriteTable {
+    /// Parses the favorites file, each line is `<stage hash> <form> <slot>` where the form is
+    /// `normal` or `battle`
+    fn parse(data: &str) -> Self {
+        let mut entries: BTreeMap<StageInfo, BTreeSet<usize>> = BTreeMap::new();
+
+        for line in data.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let [stage, form, slot] = fields[..] else {
+                log::warn!("Skipping malformed favorites line '{line}'");
+                continue;
+            };
+
+            let normal_form = match form {
+                "normal" => true,
+                "battle" => false,
+                _ => {
+                    log::warn!("Skipping malformed favorites line '{line}'");
+                    continue;
+                }
+            };
+
+            let (Ok(stage), Ok(slot)) = (
+                u64::from_str_radix(stage.trim_start_matches("0x"), 16),
+                slot.parse::<usize>(),
+            ) else {
+                log::warn!("Skipping malformed favorites line '{line}'");
+                continue;
+            };
+
+            entries
+                .entry(StageInfo {
+                    name: Hash40(stage),
+                    normal_form,
+                })
+                .or_default()
+                .insert(slot);
+        }
+
+        Self(entries)
+    }
+
+    fn serialize(&self) -> String {
+        let mut data = String::new();
+        for (info, slots) in self.0.iter() {
+            for slot in slots.iter() {
+                data.push_str(&format!(
+                    "{:#012x} {} {}\n",
+                    info.name.0,
+                    if info.normal_form { "normal" } else { "battle" },
+                    slot
+                ));
+            }
+        }
+
+        data
+    }
+}
+
+impl Favorites {
+    pub const fn new() -> Self {
+        Self {
+            entries: Stored::new(FAVORITES_PATH, FavoriteTable(BTreeMap::new())),
+        }
+    }
+
+    pub fn contains(&mut self, info: StageInfo, slot: usize) -> bool {
+        self.entries
+            .get()
+            .0
+            .get(&info)
+            .is_some_and(|slots| slots.contains(&slot))
+    }
+
+    /// Adds or removes a favorite, returning whether the alt is now a favorite
+    pub fn toggle(&mut self, info: StageInfo, slot: usize) -> bool {
+        let entries = &mut self.entries.get_mut().0;
+
+        let slots = entries.entry(info).or_default();
+        let is_favorite = if slots.remove(&slot) {
+            false
+        } else {
+            slots.insert(slot);
+            true
+        };
+
+        if slots.is_empty() {
+            entries.remove(&info);
+        }
+
+        is_favorite
+    }
+
+    pub fn take_pending_write(&mut self) -> Option<PendingWrite> {
+        self.entries.take_pending_write()
+    }
+}
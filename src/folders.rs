@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+
+use locks::RwLock;
+use smash_arc::Hash40;
+
+use crate::{manager::StageInfo, manifest};
+
+/// Highest slot that can be expressed with the `_sXX`/`_sXXX` numbering
+pub const MAX_NUMBERED_SLOT: usize = 999;
+
+static FOLDERS: RwLock<FolderTable> = RwLock::new(FolderTable::new());
+
+/// Maps the names of alt folders to the slot and form they provide, so that discovering alts
+/// is a lookup per folder instead of hashing every possible name
+pub struct FolderTable {
+    /// `normal_sXX`/`battle_sXX` and their 3 digit variants, shared by every stage
+    numbered: BTreeMap<Hash40, (usize, bool)>,
+
+    /// Folders named in `alts.toml`, keyed by stage and folder name
+    named: BTreeMap<(Hash40, Hash40), (usize, bool)>,
+
+    /// The folder name of every named alt, for building its paths when patching
+    by_slot: BTreeMap<(StageInfo, usize), Hash40>,
+
+    /// The extra slots of folders that more than one mod ships, see [`add_copy`]
+    copies: BTreeMap<(StageInfo, Hash40), Vec<usize>>,
+}
+
+impl FolderTable {
+    pub const fn new() -> Self {
+        Self {
+            numbered: BTreeMap::new(),
+            named: BTreeMap::new(),
+            by_slot: BTreeMap::new(),
+            copies: BTreeMap::new(),
+        }
+    }
+}
+
+/// The folder name an alt gets when it isn't named in `alts.toml`
+pub fn numbered_name(normal_form: bool, slot: usize) -> String {
+    let form = if normal_form { "normal" } else { "battle" };
+    format!("{form}_s{slot:02}")
+}
+
+/// Builds the folder table, this must run after the manifests are loaded
+pub fn init() {
+    let mut table = FolderTable::new();
+
+    for slot in 1..=MAX_NUMBERED_SLOT {
+        for normal_form in [true, false] {
+            table.numbered.insert(
+                Hash40::from(numbered_name(normal_form, slot).as_str()),
+                (slot, normal_form),
+            );
+        }
+    }
+
+    let manifests: &'static [manifest::AltManifest] = *manifest::MANIFESTS.read();
+    for manifest in manifests.iter() {
+        let Some(folder) = manifest.folder.as_deref() else {
+            continue;
+        };
+
+        let Some(info) = manifest.stage_info() else {
+            continue;
+        };
+
+        let folder_hash = Hash40::from(folder);
+
+        if let Some((slot, _)) = table.numbered.get(&folder_hash) {
+            log::warn!(
+                "Folder {folder} of {} is already slot {slot}, ignoring its registry entry",
+                manifest.stage
+            );
+            continue;
+        }
+
+        if let Some(existing) = table.by_slot.get(&(info, manifest.slot)) {
+            log::warn!(
+                "Slot {} of {} is already used by {}, ignoring {folder}",
+                manifest.slot,
+                manifest.stage,
+                crate::utils::string_for_hash(*existing)
+            );
+            continue;
+        }
+
+        table
+            .named
+            .insert((info.name, folder_hash), (manifest.slot, info.normal_form));
+        table.by_slot.insert((info, manifest.slot), folder_hash);
+    }
+
+    log::info!("Registered {} named alt folders", table.named.len());
+
+    *FOLDERS.write() = table;
+}
+
+/// Returns the slot and whether it is the normal form for a folder inside a stage's folder
+pub fn lookup(stage: Hash40, folder: Hash40) -> Option<(usize, bool)> {
+    let table = FOLDERS.read();
+    table
+        .named
+        .get(&(stage, folder))
+        .or_else(|| table.numbered.get(&folder))
+        .copied()
+}
+
+/// The name of the folder that holds an alt of a stage form
+pub fn folder_name(info: StageInfo, slot: usize) -> Hash40 {
+    if let Some(folder) = FOLDERS.read().by_slot.get(&(info, slot)) {
+        return *folder;
+    }
+
+    Hash40::from(numbered_name(info.normal_form, slot).as_str())
+}
+
+/// Moves an alt folder of a stage form to another slot, without touching the SD card
+pub fn remap(info: StageInfo, folder: Hash40, slot: usize) {
+    let mut table = FOLDERS.write();
+
+    table
+        .by_slot
+        .retain(|(other, _), existing| *other != info || *existing != folder);
+    table
+        .named
+        .insert((info.name, folder), (slot, info.normal_form));
+    table.by_slot.insert((info, slot), folder);
+}
+
+/// Adds a slot that loads from a folder which already provides another slot, for another mod's
+/// copy of that folder. Discovering alts lists the slot along with the folder's own
+pub fn add_copy(info: StageInfo, folder: Hash40, slot: usize) {
+    let mut table = FOLDERS.write();
+
+    table.by_slot.insert((info, slot), folder);
+    table.copies.entry((info, folder)).or_default().push(slot);
+}
+
+/// The slots added with [`add_copy`] for a folder
+pub fn copies(info: StageInfo, folder: Hash40) -> Vec<usize> {
+    FOLDERS
+        .read()
+        .copies
+        .get(&(info, folder))
+        .cloned()
+        .unwrap_or_default()
+}
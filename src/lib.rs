@@ -1,26 +1,41 @@
-use std::{
-    path::Path,
-    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
-};
+use std::{path::Path, sync::atomic::AtomicUsize};
 
-use locks::Mutex;
 use log::LevelFilter;
 use logger::StageAltsLogger;
+use manager::{AltField, AltManager, StageInfo};
 use patching::*;
+use replay::{ReplayEntry, ReplayKey};
 use resources::types::{FilesystemInfo, LoadedDirectory, ResServiceNX};
+use scene::{Scene, SceneEvent};
 use skyline::hooks::InlineCtx;
 use smash_arc::{ArcLookup, Hash40, SearchLookup};
 use smashnet::curl::Curler;
+use stats::StatsKey;
 use utils::ConcatHash;
 
-mod callbacks;
+mod binding;
+mod carousel;
+mod config;
+mod conflicts;
+mod database;
+mod favorites;
+mod folders;
 mod logger;
 mod lua;
 mod manager;
+mod manifest;
+mod msbt;
 mod music_fix;
+mod names;
+mod overlay;
 mod patching;
+mod placeholder;
+mod replay;
 mod resources;
+mod scene;
 mod search;
+mod stats;
+mod storage;
 mod utils;
 
 extern "C" {
@@ -76,11 +91,15 @@ unsafe fn initial_loading_hook(ctx: &mut skyline::hooks::InlineCtx) {
 
     let fs = FilesystemInfo::instance().unwrap();
 
+    // Keep the databases resident so that we can parse them with every mod's patches applied
+    database::request_database_files();
+
     // We lazily initialize our manager here, we don't use OnceCell because that
     // doesn't work on console
     let mut mgr = manager::MANAGER.write();
 
-    mgr.alts = alts;
+    search::start_fingerprinting(&alts);
+    mgr.set_installed_alts(alts);
 
     // We backup the filepaths for restoring stage infos on each reload before potentially patching again
     mgr.backup_filepaths = fs
@@ -99,8 +118,16 @@ unsafe fn initial_loading_hook(ctx: &mut skyline::hooks::InlineCtx) {
         .collect();
 }
 
-static ALT_NUMBER: Mutex<Option<usize>> = Mutex::new(None);
-static IS_ONLINE: AtomicBool = AtomicBool::new(false);
+/// The stages shown behind the results screen, which never have alts
+fn is_result_stage(path: Hash40) -> bool {
+    [
+        "stage/resultstage",
+        "stage/resultstage_jack",
+        "stage/resultstage_edge",
+    ]
+    .iter()
+    .any(|stage| search::is_descendant_of(path, Hash40::from(*stage)))
+}
 
 #[skyline::hook(offset = 0x3540860)]
 unsafe fn init_loaded_dir(info: &'static FilesystemInfo, index: u32) -> *mut LoadedDirectory {
@@ -114,13 +141,16 @@ unsafe fn init_loaded_dir(info: &'static FilesystemInfo, index: u32) -> *mut Loa
 
     let path = dir.path;
 
+    // Only the results screen loads a result stage
+    if is_result_stage(path.hash40()) {
+        scene::dispatch(SceneEvent::Results);
+    }
+
     // If the path is a descendant of stage and is NOT stage/common,
     // we should restore all files before performing our filesystem patching
     if search::is_descendant_of(path.hash40(), Hash40::from("stage"))
         && !search::is_descendant_of(path.hash40(), Hash40::from("stage/common"))
-        && !search::is_descendant_of(path.hash40(), Hash40::from("stage/resultstage"))
-        && !search::is_descendant_of(path.hash40(), Hash40::from("stage/resultstage_jack"))
-        && !search::is_descendant_of(path.hash40(), Hash40::from("stage/resultstage_edge"))
+        && !is_result_stage(path.hash40())
     {
         // "pretty" hash gives us a segmented list of hash path segments that we
         // can use to ensure that we are an immediate descendant of a
@@ -145,12 +175,25 @@ unsafe fn init_loaded_dir(info: &'static FilesystemInfo, index: u32) -> *mut Loa
     // Again, ensure that we are a stage folder that is not stage/common
     if search::is_descendant_of(path.hash40(), Hash40::from("stage"))
         && !search::is_descendant_of(path.hash40(), Hash40::from("stage/common"))
-        && !search::is_descendant_of(path.hash40(), Hash40::from("stage/resultstage"))
-        && !search::is_descendant_of(path.hash40(), Hash40::from("stage/resultstage_jack"))
-        && !search::is_descendant_of(path.hash40(), Hash40::from("stage/resultstage_edge"))
+        && !is_result_stage(path.hash40())
     {
-        // TODO: Change this to using the alt manager
-        let Some(alt) = *ALT_NUMBER.lock() else {
+        // Every folder under a stage form folder shares the alt that was chosen for
+        // that form folder, so we resolve the alt using the `stage/<name>/<form>` prefix
+        let pretty = path.hash40().pretty();
+        if pretty.components().len() < 3 {
+            return result;
+        }
+
+        let Some(folder) = StageInfo::from_path(pretty.sub_range(3)) else {
+            return result;
+        };
+
+        let alt = manager::MANAGER.write().pending_alt(folder);
+        if pretty.components().len() == 3 {
+            conflicts::set_loading_alt(folder, alt.unwrap_or_default());
+        }
+
+        let Some(alt) = alt else {
             return result;
         };
 
@@ -190,12 +233,22 @@ unsafe fn init_loaded_dir(info: &'static FilesystemInfo, index: u32) -> *mut Loa
     result
 }
 
-#[skyline::hook(offset = 0x25fdf58, inline)]
-unsafe fn prepare_for_load(ctx: &InlineCtx) {
-    if IS_ONLINE.load(Ordering::Acquire) {
+/// Counts a play of the alt a match resolved to. Only the player's own matches count, not
+/// training, replays or stages that load for menus. The stats are saved once the match is left
+fn record_play(mgr: &mut AltManager, scene: Scene, info: StageInfo, alt: Option<usize>) {
+    if scene != Scene::LocalMatch && !scene.is_online() {
         return;
     }
 
+    mgr.stats.record(StatsKey {
+        stage: info.name,
+        normal_form: info.normal_form,
+        slot: alt.unwrap_or_default(),
+    });
+}
+
+#[skyline::hook(offset = 0x25fdf58, inline)]
+unsafe fn prepare_for_load(ctx: &InlineCtx) {
     let search = FilesystemInfo::instance().unwrap().search();
 
     let Ok(path) = search.get_path_list_entry_from_hash(*ctx.registers[8].x.as_ref()) else {
@@ -214,27 +267,33 @@ unsafe fn prepare_for_load(ctx: &InlineCtx) {
         return;
     };
 
-    let mut mgr = manager::MANAGER.write();
-    *ALT_NUMBER.lock() = mgr.fetch_advance();
-}
-
-unsafe fn get_place_id(stage_id: usize) -> usize {
-    let start = (skyline::hooks::getRegionAddress(skyline::hooks::Region::Text) as *const u8)
-        .add(0x45489b8);
-
-    let stage_entry = start.add(stage_id * 0x48);
-    let place_id = stage_entry.add(0x3c) as *const u32;
-    *place_id as usize
-}
+    // The path being prepared is either the stage form folder or one of its children
+    let Some(folder) = StageInfo::from_path(path.path.hash40())
+        .or_else(|| StageInfo::from_path(parent_path.path.hash40()))
+    else {
+        log::warn!(
+            "Failed to get the stage form folder for {}",
+            path.path.hash40().pretty()
+        );
+        return;
+    };
 
-unsafe fn get_place_hash(place_id: usize) -> hash40::Hash40 {
-    let start = (skyline::hooks::getRegionAddress(skyline::hooks::Region::Text) as *const u8)
-        .add(0x4547420);
+    let event = if folder.name == Hash40::from("training") {
+        SceneEvent::TrainingLoad
+    } else {
+        SceneEvent::MatchLoad
+    };
 
-    let stage_place_entry = start.add(place_id * 0x28) as *const u64;
-    let hash = *stage_place_entry;
+    // Online matches and replays get their alt from the BGM id instead
+    let scene = scene::dispatch(event);
+    if scene.is_online() || scene == Scene::Replay {
+        return;
+    }
 
-    hash40::Hash40(hash)
+    let mut mgr = manager::MANAGER.write();
+    let alt = mgr.fetch_advance();
+    mgr.set_pending_alt(folder, alt);
+    record_play(&mut mgr, scene, folder, alt);
 }
 
 #[skyline::hook(offset = 0x16b9eb4, inline)]
@@ -246,44 +305,118 @@ unsafe fn fetch_current_alt_from_bgm_id(ctx: &InlineCtx) {
     let bgm_id = *bgm_id_ptr;
     let bgm_hash = bgm_id & 0xFF_FFFFFFFF;
 
-    let mgr = manager::MANAGER.read();
-    let cache = mgr.music_cache.as_ref().unwrap();
+    let mut mgr = manager::MANAGER.write();
+    mgr.ensure_databases();
+
     let stage_id = *(*ctx.registers[1].x.as_ref() as *const u32) as usize;
 
-    let hash = get_place_hash(get_place_id(stage_id));
-    if !cache.is_song_allowed(hash40::Hash40(bgm_hash)) {
-        let new_song = cache.get_random_song(hash);
+    let field = AltField::decode((bgm_id >> 40) & 0xFFFF);
+
+    // Without the stage we can't key the alt by its folder, so it waits for the first folder
+    // of the picked form to load instead
+    let Some(stage) = mgr.place_for_stage_id(stage_id) else {
+        log::warn!("Failed to find the stage place for stage id {stage_id:#x}");
+        scene::dispatch(SceneEvent::MatchLoad);
+        mgr.set_unresolved_alt(field);
+        return;
+    };
+
+    let hash = hash40::Hash40(stage.0);
+
+    // If the databases failed to parse we can't tell which songs are safe to play
+    if let Some(cache) = mgr.music_cache.as_mut() {
+        if let Some(new_song) = cache.substitute_song(hash, hash40::Hash40(bgm_hash)) {
+            log::info!(
+                "Replacing {} with {} on {}",
+                utils::string_for_hash(Hash40(bgm_hash)),
+                utils::string_for_hash(Hash40(new_song.0)),
+                utils::string_for_hash(stage),
+            );
+
+            *bgm_id_ptr = (*bgm_id_ptr & 0xFFFFFF00_00000000) | new_song.0;
+        }
+    }
+
+    // The replay saves the BGM id after the music fix, so that is what matches are keyed by
+    let key = ReplayKey {
+        stage_id: stage_id as u32,
+        bgm_id: *bgm_id_ptr,
+    };
+
+    let scene = scene::dispatch(SceneEvent::MatchLoad);
+    let (info, alt) = if scene == Scene::Replay {
+        replay::resolve_alt(&mut mgr, key, stage, field)
+    } else {
+        // The alt field says which form was picked, so the alt goes to that form's folder
+        let info = field.stage_info(stage);
+        let alt = mgr.resolve_alt_field(stage, field);
+        mgr.replays.record(
+            key,
+            ReplayEntry {
+                info,
+                slot: alt.unwrap_or_default(),
+            },
+        );
+        (info, alt)
+    };
+
+    mgr.set_pending_alt(info, alt);
 
-        *bgm_id_ptr = (*bgm_id_ptr & 0xFFFFFF00_00000000) | new_song.0;
+    // Local matches pick their alt once the stage loads, see prepare_for_load
+    if scene.is_online() {
+        record_play(&mut mgr, scene, info, alt);
     }
+}
 
-    let alt_id = (bgm_id >> 40) & 0xFFFF;
-    *ALT_NUMBER.lock() = mgr.fetch_alt_for_stage(smash_arc::Hash40(hash.0), alt_id as usize);
+/// A tag filter only lasts as long as the local stage select session that set it
+fn clear_tag_filter() {
+    manager::MANAGER.write().set_tag_filter(None);
 }
 
 #[skyline::hook(offset = 0x22d9e90, inline)]
 unsafe fn online_melee_any_scene_create(_: &InlineCtx) {
-    IS_ONLINE.store(true, Ordering::Release);
+    scene::dispatch(SceneEvent::OnlineQuickplay);
+    clear_tag_filter();
 }
 
 #[skyline::hook(offset = 0x22d9dc0, inline)]
 unsafe fn bg_matchmaking_seq(_: &InlineCtx) {
-    IS_ONLINE.store(true, Ordering::Release);
+    scene::dispatch(SceneEvent::BackgroundMatchmaking);
+    clear_tag_filter();
 }
 
 #[skyline::hook(offset = 0x22d9cf0, inline)]
 unsafe fn arena_seq(_: &InlineCtx) {
-    IS_ONLINE.store(true, Ordering::Release);
+    scene::dispatch(SceneEvent::OnlineArena);
+    clear_tag_filter();
 }
 
 #[skyline::hook(offset = 0x235a64c, inline)]
 unsafe fn main_menu(_: &InlineCtx) {
-    IS_ONLINE.store(false, Ordering::Release);
+    scene::dispatch(SceneEvent::MainMenu);
+    clear_tag_filter();
+    storage::save_pending();
 }
 
 #[no_mangle]
 pub extern "C" fn get_current_stage_alt() -> usize {
-    ALT_NUMBER.lock().unwrap_or_default()
+    manager::MANAGER.read().last_alt.unwrap_or_default()
+}
+
+/// Returns the content fingerprint of an installed alt, or 0 if the alt is not installed
+#[no_mangle]
+pub extern "C" fn get_stage_alt_fingerprint(stage: u64, normal_form: bool, slot: usize) -> u64 {
+    manager::MANAGER
+        .read()
+        .find_alt_by_slot(
+            StageInfo {
+                name: Hash40(stage),
+                normal_form,
+            },
+            slot,
+        )
+        .map(|alt| alt.fingerprint)
+        .unwrap_or_default()
 }
 
 #[skyline::main(name = "stage-alts")]
@@ -313,6 +446,14 @@ pub fn main() {
 
     utils::init_hash_lookup(false);
 
+    config::load();
+    manifest::load();
+    folders::init();
+    conflicts::check();
+    placeholder::install();
+    names::install();
+    overlay::install();
+
     check_download_hashes();
 
     skyline::install_hooks!(
@@ -326,7 +467,5 @@ pub fn main() {
         main_menu
     );
 
-    callbacks::install();
-
     lua::install();
 }
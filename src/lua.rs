@@ -1,173 +1,393 @@
-use std::{collections::BTreeMap, io::Cursor, path::Path, ptr::NonNull};
+use std::ptr::NonNull;
 
-use prc::ParamKind;
 use rlua_lua53_sys as lua;
 use skyline::hooks::InlineCtx;
-use smash_arc::{ArcLookup, Hash40};
+use smash_arc::Hash40;
 
 use crate::{
-    manager::{SelectedAltInfo, StageInfo, StageKind, UiPaths, MANAGER},
-    resources::{self, types::FilesystemInfo},
+    binding::{self, Function, IntoLua, State},
+    carousel::{self, CarouselView, Step},
+    config::{MusicPolicy, CONFIG},
+    lua_functions,
+    manager::{
+        self, AltField, AltInfo, AltManager, PlayableAlts, SelectedAltInfo, StageInfo, StageKind,
+        UiPaths, UiVariant, MANAGER,
+    },
+    names, resources,
+    stats::StatsKey,
     utils::ConcatHash,
 };
 
-extern "C" fn send_message(state: *mut lua::lua_State) -> i32 {
-    unsafe {
-        let value = skyline::from_c_str(lua::lua_tostring(state, -1) as _);
-        log::info!("Lua says: {}", value);
-        lua::lua_pop(state, 1);
-        0
-    }
+/// Bumped whenever a function is added to `Alts` or the arguments of one change, so scripts
+/// can tell what the plugin they run against supports. The bundled scripts compare it to their
+/// `ALTS_API_VERSION` once in setup, which has to match this when they are shipped together.
+///
+/// - 2: the plugin steps through alts with `next_alt`/`prev_alt` and filters them by tag
+/// - 3: the favorites-only cycle is read from the config instead of taken from the script
+/// - 4: `favorite_button` names the button that toggles favorites
+/// - 5: `get_alt_name_label` gives the stage's own name for alts without one
+/// - 6: `write_alt_field_to_bgm_id` takes the form of the preview
+const API_VERSION: i64 = 6;
+
+static ALTS_API: &[Function] = lua_functions![
+    api_version,
+    has,
+    send_message,
+    print_panel_name,
+    get_panel_alt_count,
+    get_alt_texture_index,
+    get_alt_variant_texture_index,
+    get_alt_fingerprint,
+    get_alts,
+    get_alt_name_label,
+    get_alt_play_count,
+    get_random_alt,
+    export_alt_stats,
+    next_alt,
+    prev_alt,
+    current,
+    select_alt,
+    toggle_favorite,
+    favorite_button,
+    get_favorites,
+    next_favorite,
+    set_tag_filter,
+    set_alts,
+    write_alt_field_to_bgm_id,
+];
+
+/// Functions for debugging scripts, kept out of `Alts` so they can change without bumping
+/// [`API_VERSION`]
+static DEBUG_API: &[Function] = lua_functions![print_panel_name, dump_alts, dump_selection];
+
+/// The globals that are registered on the stage select's Lua state
+static NAMESPACES: &[(&str, &[Function])] = &[("Alts", ALTS_API), ("AltsDebug", DEBUG_API)];
+
+// Panels and forms are `i64` in every function, since scripts pass -1 for previews that show
+// no stage. Forms are the stage select's form ids: 0 is the normal form, and the battlefield
+// (1) and omega (2) forms load the battle folder
+
+/// The stage on a panel
+fn panel_stage(mgr: &AltManager, panel: i64) -> Option<Hash40> {
+    let panel = usize::try_from(panel).ok()?;
+    mgr.index_to_hash.get(&panel).copied()
 }
 
-extern "C" fn print_panel_name(state: *mut lua::lua_State) -> i32 {
-    unsafe {
-        let index = lua::lua_tointegerx(state, -1, std::ptr::null_mut()) as usize;
-        lua::lua_pop(state, 1);
+/// The stage on a panel and the form id, if the preview shows a stage
+fn panel_form(mgr: &AltManager, panel: i64, form: i64) -> Option<(Hash40, usize)> {
+    let form = usize::try_from(form).ok()?;
+    panel_stage(mgr, panel).map(|stage| (stage, form))
+}
 
-        if usize::MAX == index {
-            return 0;
-        }
+fn panel_info(mgr: &AltManager, panel: i64, form: i64) -> Option<StageInfo> {
+    panel_form(mgr, panel, form).map(|(name, form)| StageInfo {
+        name,
+        normal_form: form == 0,
+    })
+}
 
-        let Some(hash) = MANAGER.read().index_to_hash.get(&index).copied() else {
-            log::warn!("No hash for index {index}");
-            return 0;
-        };
+fn api_version() -> i64 {
+    API_VERSION
+}
+
+/// Whether `Alts` has a function with the given name
+fn has(name: String) -> bool {
+    ALTS_API.iter().any(|function| function.name() == name)
+}
 
-        log::info!("Index {index}: {}", crate::utils::string_for_hash(hash));
+fn send_message(message: String) {
+    log::info!("Lua says: {}", message);
+}
 
-        0
+fn print_panel_name(panel: i64) {
+    if panel < 0 {
+        return;
     }
+
+    let Some(hash) = panel_stage(&MANAGER.read(), panel) else {
+        log::warn!("No hash for index {panel}");
+        return;
+    };
+
+    log::info!("Index {panel}: {}", crate::utils::string_for_hash(hash));
 }
 
-extern "C" fn get_panel_alt_count(state: *mut lua::lua_State) -> i32 {
-    unsafe {
-        let form_id = lua::lua_tointegerx(state, -1, std::ptr::null_mut()) as usize;
-        lua::lua_pop(state, 1);
+/// Logs every selectable alt of a panel
+fn dump_alts(panel: i64, form: i64) {
+    let mgr = MANAGER.read();
 
-        let panel_id = lua::lua_tointegerx(state, -1, std::ptr::null_mut()) as usize;
-        lua::lua_pop(state, 1);
+    let Some(info) = panel_info(&mgr, panel, form) else {
+        log::info!("Panel {panel} has no stage");
+        return;
+    };
 
-        if usize::MAX == panel_id {
-            lua::lua_pushinteger(state, 0);
-            return 1;
-        }
+    let alts = mgr.selectable_alts(info);
+    log::info!(
+        "{} ({}) has {} selectable alts",
+        crate::utils::string_for_hash(info.name),
+        if info.normal_form { "normal" } else { "battle" },
+        alts.len()
+    );
+
+    for (position, alt) in alts.iter().enumerate() {
+        log::info!(
+            "  {}: slot {:02}, fingerprint {:#x}, tags {:?}",
+            position + 1,
+            alt.slot_value,
+            alt.fingerprint,
+            alt.tags
+        );
+    }
+}
 
-        let Some(hash) = MANAGER.read().index_to_hash.get(&panel_id).copied() else {
-            log::warn!("No hash for index {panel_id}");
-            lua::lua_pushinteger(state, 0);
-            return 1;
-        };
+/// Logs the alts that were picked for the current set
+fn dump_selection() {
+    let mgr = MANAGER.read();
 
-        let count = MANAGER
-            .read()
-            .alts
-            .get(&StageInfo {
-                name: hash,
-                normal_form: form_id == 0,
-            })
-            .map(|v| v.len())
-            .unwrap_or_default();
-
-        lua::lua_pushinteger(state, count as i64);
-        1
+    let Some(selected) = mgr.selected_alts.as_ref() else {
+        log::info!("No alts are selected");
+        return;
+    };
+
+    let alts = match &selected.playable {
+        PlayableAlts::OneStage(first) => vec![*first],
+        PlayableAlts::TwoStages(alts) => alts.to_vec(),
+        PlayableAlts::ThreeStages(alts) => alts.to_vec(),
+    };
+
+    for alt in alts {
+        log::info!(
+            "{} ({}): alt {}",
+            crate::utils::string_for_hash(alt.stage_info.name),
+            if alt.stage_info.normal_form {
+                "normal"
+            } else {
+                "battle"
+            },
+            alt.index
+        );
     }
+    log::info!("Currently playing #{}", selected.current_index);
 }
 
-extern "C" fn get_alt_texture_index(state: *mut lua::lua_State) -> i32 {
-    unsafe {
-        let alt_id = lua::lua_tointegerx(state, -1, std::ptr::null_mut()) as usize;
-        lua::lua_pop(state, 1);
+fn get_panel_alt_count(panel: i64, form: i64) -> usize {
+    if panel < 0 {
+        return 0;
+    }
 
-        let form_id = lua::lua_tointegerx(state, -1, std::ptr::null_mut()) as usize;
-        lua::lua_pop(state, 1);
+    let mgr = MANAGER.read();
 
-        let panel_id = lua::lua_tointegerx(state, -1, std::ptr::null_mut()) as usize;
-        lua::lua_pop(state, 1);
+    let Some(info) = panel_info(&mgr, panel, form) else {
+        log::warn!("No hash for index {panel}");
+        return 0;
+    };
 
-        if usize::MAX == panel_id {
-            lua::lua_pushinteger(state, -1);
-            return 1;
-        }
+    mgr.selectable_alt_count(info)
+}
 
-        let Some(hash) = MANAGER.read().index_to_hash.get(&panel_id).copied() else {
-            log::warn!("No hash for index {panel_id}");
-            lua::lua_pushinteger(state, -1);
-            return 1;
-        };
+fn get_alt_texture_index(panel: i64, form: i64, alt: usize) -> i64 {
+    let mgr = MANAGER.read();
 
-        let mgr = MANAGER.read();
-
-        let paths = if alt_id == 0 {
-            UiPaths::new(StageKind::from(hash), 0)
-        } else {
-            let Some(alt) = mgr
-                .alts
-                .get(&StageInfo {
-                    name: hash,
-                    normal_form: form_id == 0,
-                })
-                .and_then(|alts| alts.get(alt_id - 1))
-            else {
-                lua::lua_pushinteger(state, -1);
-                return 1;
-            };
-
-            alt.ui_paths
-        };
+    let Some((stage, form)) = panel_form(&mgr, panel, form) else {
+        return -1;
+    };
 
-        let arc = FilesystemInfo::instance().unwrap().arc();
+    mgr.preview_texture_index(stage, form, alt)
+        .map(|index| index as i64)
+        .unwrap_or(-1)
+}
 
-        let path = match form_id {
-            1 => paths.battle,
-            2 => paths.end,
-            _ => paths.normal,
-        };
+fn get_alt_variant_texture_index(panel: i64, form: i64, alt: usize, variant: usize) -> i64 {
+    let Some(variant) = UiVariant::from_index(variant) else {
+        log::warn!("Unknown UI variant {variant}");
+        return -1;
+    };
 
-        let Ok(index) = arc.get_file_path_index_from_hash(path) else {
-            log::warn!("Could not get file path index for {}", path.pretty());
-            lua::lua_pushinteger(state, -1);
-            return 1;
-        };
+    let mgr = MANAGER.read();
+
+    let Some(info) = panel_info(&mgr, panel, form) else {
+        return -1;
+    };
+
+    let base = UiPaths::new(StageKind::from(info.name), 0).get(variant);
+
+    // Alts that don't provide a texture for this variant use the base stage's texture
+    let index = mgr
+        .nth_alt(info, alt)
+        .and_then(|alt| manager::texture_index(alt.ui_paths.get(variant)))
+        .or_else(|| manager::texture_index(base));
+
+    match index {
+        Some(index) => index as i64,
+        None => {
+            log::warn!("Could not get file path index for {}", base.pretty());
+            -1
+        }
+    }
+}
+
+fn get_alt_fingerprint(panel: i64, form: i64, alt: usize) -> u64 {
+    let mgr = MANAGER.read();
+
+    panel_info(&mgr, panel, form)
+        .and_then(|info| mgr.nth_alt(info, alt))
+        .map(|alt| alt.fingerprint)
+        .unwrap_or_default()
+}
+
+/// An entry of the table returned by [`get_alts`]
+struct AltEntry {
+    /// The alt's position in the list, 0 being the base stage
+    index: usize,
+    alt: AltInfo,
+    base: UiPaths,
+    language: &'static str,
+}
+
+impl IntoLua for AltEntry {
+    unsafe fn push(self, state: State) -> i32 {
+        let Self {
+            index,
+            alt,
+            base,
+            language,
+        } = self;
+
+        lua::lua_createtable(state, 0, 8);
+
+        binding::set_field(state, "index\0", index);
+        binding::set_field(state, "slot\0", alt.slot_value);
+        binding::set_field(
+            state,
+            "name\0",
+            alt.manifest
+                .and_then(|manifest| manifest.display_name(language)),
+        );
+        binding::set_field(
+            state,
+            "author\0",
+            alt.manifest.and_then(|manifest| manifest.author.as_deref()),
+        );
+        binding::set_field(state, "tags\0", alt.tags);
+        binding::set_field(state, "wifi_safe\0", alt.wifi_safe);
+        binding::set_field(state, "fingerprint\0", alt.fingerprint);
+
+        // Variants the alt doesn't provide use the base stage's texture, like
+        // get_alt_variant_texture_index
+        lua::lua_createtable(state, 0, 5);
+        for (variant, key) in [
+            (UiVariant::Icon, "icon\0"),
+            (UiVariant::Panel, "panel\0"),
+            (UiVariant::Normal, "normal\0"),
+            (UiVariant::Battle, "battle\0"),
+            (UiVariant::End, "end\0"),
+        ] {
+            let index = manager::texture_index(alt.ui_paths.get(variant))
+                .or_else(|| manager::texture_index(base.get(variant)));
+            binding::set_field(state, key, index);
+        }
+        lua::lua_setfield(state, -2, "textures\0".as_ptr() as _);
 
-        lua::lua_pushinteger(state, index.0 as i64);
         1
     }
 }
 
-extern "C" fn write_alt_field_to_bgm_id(state: *mut lua::lua_State) -> i32 {
-    unsafe {
-        let mgr = MANAGER.write();
+/// Every selectable alt of a panel, in list order. Each entry has `index`, `slot`, `name`,
+/// `author`, `tags`, `wifi_safe`, `fingerprint` and a `textures` table keyed by variant
+/// (`icon`, `panel`, `normal`, `battle`, `end`). Locked stages have no selectable alts, like
+/// in [`get_panel_alt_count`]
+fn get_alts(panel: i64, form: i64) -> Vec<AltEntry> {
+    let mgr = MANAGER.read();
 
-        let alt_id = lua::lua_tointegerx(state, -1, std::ptr::null_mut()) as usize;
-        lua::lua_pop(state, 1);
+    let Some(info) = panel_info(&mgr, panel, form).filter(|info| !mgr.is_locked(*info)) else {
+        return vec![];
+    };
 
-        let stage_preview_idx = lua::lua_tointegerx(state, -1, std::ptr::null_mut()) as usize;
-        lua::lua_pop(state, 1);
+    let base = UiPaths::new(StageKind::from(info.name), 0);
+    let language = names::current_language();
+
+    mgr.selectable_alts(info)
+        .iter()
+        .copied()
+        .enumerate()
+        .map(|(position, alt)| AltEntry {
+            index: position + 1,
+            alt,
+            base,
+            language,
+        })
+        .collect()
+}
+
+/// The message label of the alt's name. The base stage and alts without a name get the stage's
+/// own name, so that a script can put it back after showing a named alt. Nil if the panel shows
+/// no stage
+fn get_alt_name_label(panel: i64, form: i64, alt: usize) -> Option<String> {
+    let mgr = MANAGER.read();
+    let info = panel_info(&mgr, panel, form)?;
+
+    let language = names::current_language();
+    mgr.nth_alt(info, alt)
+        .and_then(|alt| alt.manifest)
+        .filter(|manifest| manifest.display_name(language).is_some())
+        .map(names::label_for)
+        .or_else(|| {
+            mgr.place_name_ids
+                .get(&info.name)
+                .map(|name_id| names::stage_label(name_id))
+        })
+}
+
+/// Packs the alt picked on a preview into its BGM id. The alt's slot is written rather than
+/// its position in the list, so that players with other filters or policies load the same alt
+fn write_alt_field_to_bgm_id(preview: usize, form: i64, alt: usize) {
+    unsafe {
+        let mut mgr = MANAGER.write();
 
-        match mgr.current_singleton {
+        let singleton = mgr.current_singleton;
+        match singleton {
             Some(mut ptr) => {
                 let object_ptr: *mut u64 = (ptr.as_mut() as *mut () as *mut u8)
-                    .add(0xf8 + 0x28 * stage_preview_idx)
+                    .add(0xf8 + 0x28 * preview)
                     .cast();
 
+                let panel = *(object_ptr.add(3) as *const u32) as usize;
+                let stage = mgr.index_to_hash.get(&panel).copied();
+
+                // Previews without a stage have no form, their alt field is the base stage's
+                let form = usize::try_from(form).unwrap_or_default();
+                let slot = stage
+                    .and_then(|stage| {
+                        let info = StageInfo {
+                            name: stage,
+                            normal_form: form == 0,
+                        };
+                        mgr.nth_alt(info, alt)
+                    })
+                    .map(|alt| alt.slot_value)
+                    .unwrap_or_default();
+
                 *object_ptr.add(2) &= 0xFF0000FF_FFFFFFFF;
-                *object_ptr.add(2) |= ((alt_id & 0xFFFF) as u64) << 40;
-
-                // let ui_hash = *object_ptr.add(2) & 0xFF_FFFFFFFF;
-                // if ui_hash == hash40::hash40("ui_bgm_random").0 {
-                //     let bgm_id_ptr = object_ptr.add(2);
-                //     let panel = *(object_ptr.add(3) as *const u32);
-                //     if let Some(hash) = mgr.index_to_hash.get(&(panel as usize)) {
-                //         println!("{:#x}", hash.0);
-                //         let new_song = mgr
-                //             .music_cache
-                //             .as_ref()
-                //             .unwrap()
-                //             .get_random_song(hash40::Hash40(hash.0));
-                //         *bgm_id_ptr = (*bgm_id_ptr & 0xFFFFFF00_00000000) | new_song.0;
-                //     }
-                // }
+                *object_ptr.add(2) |= AltField::Slot { form, slot }.encode() << 40;
+
+                // Random music on the stage select screen should come from the playlist of
+                // the stage that was picked rather than the game's global random pick
+                let bgm_id_ptr = object_ptr.add(2);
+                let ui_hash = *bgm_id_ptr & 0xFF_FFFFFFFF;
+                if ui_hash == hash40::hash40("ui_bgm_random").0 {
+                    match (stage, mgr.music_cache.as_mut()) {
+                        (Some(stage), Some(cache)) if cache.policy != MusicPolicy::Off => {
+                            let new_song = cache.get_random_song(hash40::Hash40(stage.0));
+                            log::info!(
+                                "Resolved random music on {} to {}",
+                                crate::utils::string_for_hash(stage),
+                                crate::utils::string_for_hash(Hash40(new_song.0))
+                            );
+                            *bgm_id_ptr = (*bgm_id_ptr & 0xFFFFFF00_00000000) | new_song.0;
+                        }
+                        _ => {}
+                    }
+                }
 
                 log::info!("Set BGM id to {:#x}", *object_ptr.add(2));
             }
@@ -177,57 +397,209 @@ extern "C" fn write_alt_field_to_bgm_id(state: *mut lua::lua_State) -> i32 {
                 );
             }
         }
+    }
+}
+
+/// How many times the alt was played and when it was last played
+fn get_alt_play_count(panel: i64, form: i64, alt: usize) -> (u32, u64) {
+    let mut mgr = MANAGER.write();
+
+    let Some(info) = panel_info(&mgr, panel, form) else {
+        return (0, 0);
+    };
+
+    let slot = mgr
+        .nth_alt(info, alt)
+        .map(|alt| alt.slot_value)
+        .unwrap_or_default();
 
-        0
+    let stats = mgr.stats.get(StatsKey {
+        stage: info.name,
+        normal_form: info.normal_form,
+        slot,
+    });
+
+    (stats.plays, stats.last_played)
+}
+
+/// A random position in the panel's alt list, 0 being the base stage. `least_played` only
+/// picks from the least played alts
+fn get_random_alt(panel: i64, form: i64, least_played: bool) -> usize {
+    let mut mgr = MANAGER.write();
+
+    let Some(info) = panel_info(&mgr, panel, form) else {
+        return 0;
+    };
+
+    mgr.random_alt_index(info, least_played)
+}
+
+fn export_alt_stats() {
+    let writes = MANAGER.write().stats.export();
+    for write in writes {
+        write.write();
     }
 }
 
-extern "C" fn set_alts(state: *mut lua::lua_State) -> i32 {
-    unsafe {
-        let mut mgr = MANAGER.write();
+/// Pushed as the selected alt followed by the textures of it and its left and right
+/// neighbors, textures that don't exist are pushed as nil
+impl IntoLua for CarouselView {
+    unsafe fn push(self, state: State) -> i32 {
+        (self.selected, self.texture, self.left, self.right).push(state)
+    }
+}
 
-        let pop_info = || {
-            let form = lua::lua_tointegerx(state, -1, std::ptr::null_mut());
-            lua::lua_pop(state, 1);
+/// The stage and form a preview shows, previews without a stage have no selection
+fn preview_stage(mgr: &AltManager, panel: i64, form: i64) -> (Option<Hash40>, usize) {
+    match panel_form(mgr, panel, form) {
+        Some((stage, form)) => (Some(stage), form),
+        None => (None, 0),
+    }
+}
 
-            let panel = lua::lua_tointegerx(state, -1, std::ptr::null_mut());
-            lua::lua_pop(state, 1);
+/// Moves the selection of a preview, `favorites_only` only cycles through favorites and
+/// defaults to the config
+fn step_carousel(
+    preview: usize,
+    panel: i64,
+    form: i64,
+    step: Step,
+    favorites_only: Option<bool>,
+) -> CarouselView {
+    let favorites_only =
+        favorites_only.unwrap_or_else(|| CONFIG.read().stage_select.favorites_only);
+
+    let mut mgr = MANAGER.write();
+    let (stage, form) = preview_stage(&mgr, panel, form);
+
+    carousel::update(&mut mgr, preview, stage, form, step, favorites_only)
+}
 
-            let alt = lua::lua_tointegerx(state, -1, std::ptr::null_mut());
-            lua::lua_pop(state, 1);
+fn next_alt(preview: usize, panel: i64, form: i64, favorites_only: Option<bool>) -> CarouselView {
+    step_carousel(preview, panel, form, Step::Next, favorites_only)
+}
 
-            if form < 0 || panel < 0 || alt < 0 {
-                None
-            } else {
-                mgr.index_to_hash
-                    .get(&(panel as usize))
-                    .copied()
-                    .map(|name| SelectedAltInfo {
-                        index: alt as usize,
-                        stage_info: StageInfo {
-                            name,
-                            normal_form: form == 0,
-                        },
-                    })
-            }
+fn prev_alt(preview: usize, panel: i64, form: i64, favorites_only: Option<bool>) -> CarouselView {
+    step_carousel(preview, panel, form, Step::Prev, favorites_only)
+}
+
+fn current(preview: usize, panel: i64, form: i64, favorites_only: Option<bool>) -> CarouselView {
+    step_carousel(preview, panel, form, Step::Stay, favorites_only)
+}
+
+/// Sets the selection of a preview
+fn select_alt(preview: usize, panel: i64, form: i64, alt: usize) -> CarouselView {
+    let mut mgr = MANAGER.write();
+    let (stage, form) = preview_stage(&mgr, panel, form);
+
+    carousel::select(&mut mgr, preview, stage, form, alt)
+}
+
+/// Toggles whether an alt is a favorite, returns whether it now is one
+fn toggle_favorite(panel: i64, form: i64, alt: usize) -> bool {
+    let (is_favorite, write) = {
+        let mut mgr = MANAGER.write();
+
+        let Some(info) = panel_info(&mgr, panel, form) else {
+            return false;
         };
 
-        let third = pop_info();
-        let second = pop_info();
-        let first = pop_info();
+        (
+            mgr.toggle_favorite(info, alt),
+            mgr.favorites.take_pending_write(),
+        )
+    };
+
+    if let Some(write) = write {
+        write.write();
+    }
+
+    is_favorite
+}
+
+/// The button that toggles favorites, named like the game's `LIB_BUTTON_*` inputs. Empty when
+/// the toggle shouldn't be bound
+fn favorite_button() -> String {
+    CONFIG.read().stage_select.favorite_button.to_lowercase()
+}
+
+/// The positions of the panel's favorite alts
+fn get_favorites(panel: i64, form: i64) -> Vec<usize> {
+    let mut mgr = MANAGER.write();
+
+    match panel_info(&mgr, panel, form) {
+        Some(info) => mgr.favorite_indices(info),
+        None => vec![],
+    }
+}
+
+/// The position of the next favorite alt in the given direction, or the current position if
+/// the panel has no favorites
+fn next_favorite(panel: i64, form: i64, alt: usize, forward: bool) -> usize {
+    let mut mgr = MANAGER.write();
+
+    let Some(info) = panel_info(&mgr, panel, form) else {
+        return alt;
+    };
+
+    mgr.next_favorite(info, alt, forward)
+}
+
+/// Sets the tag that selectable alts must have, nil clears the filter. Only local stage
+/// selects can filter, the filter is cleared when the player leaves local play. It is shared by
+/// every stage select, so scripts set it in setup and clear it again when the player backs out
+fn set_tag_filter(tag: Option<String>) {
+    if crate::scene::current().is_online() {
+        log::warn!("Ignoring the tag filter {tag:?}, it only applies to local play");
+        return;
+    }
+
+    MANAGER.write().set_tag_filter(tag);
+}
+
+/// Takes the alt, panel and form of each of the three previews, previews that aren't used
+/// have negative values
+#[allow(clippy::too_many_arguments)]
+fn set_alts(
+    first_alt: i64,
+    first_panel: i64,
+    first_form: i64,
+    second_alt: i64,
+    second_panel: i64,
+    second_form: i64,
+    third_alt: i64,
+    third_panel: i64,
+    third_form: i64,
+) {
+    let mut mgr = MANAGER.write();
 
-        if let Some(first) = first {
-            mgr.set_alts(first, second, third);
+    let info = |alt: i64, panel: i64, form: i64| {
+        if form < 0 || alt < 0 {
+            return None;
         }
 
-        0
+        panel_info(&mgr, panel, form).map(|stage_info| SelectedAltInfo {
+            index: alt as usize,
+            stage_info,
+        })
+    };
+
+    let first = info(first_alt, first_panel, first_form);
+    let second = info(second_alt, second_panel, second_form);
+    let third = info(third_alt, third_panel, third_form);
+
+    if let Some(first) = first {
+        mgr.set_alts(first, second, third);
     }
 }
 
+/// Creates a table backed by a metatable holding the functions and stores it as a global.
+/// Only the public Lua API is used, and running this again on the same state (like when the
+/// key context is rebuilt) replaces the functions of the existing metatable
 unsafe fn push_new_singleton(
     lua_state: *mut lua::lua_State,
     name: &'static str,
-    registry: &[lua::luaL_Reg],
+    functions: &'static [Function],
 ) {
     let real_name = format!("{}\0", name);
     let meta_name = format!("Metatable{}\0", name);
@@ -235,59 +607,34 @@ unsafe fn push_new_singleton(
     lua::lua_pushvalue(lua_state, -1);
     lua::lua_setfield(lua_state, -2, "__index\0".as_ptr() as _);
 
-    lua::luaL_setfuncs(lua_state, registry.as_ptr(), 0);
-    lua::lua_pop(lua_state, 1);
+    for function in functions {
+        lua::lua_pushcclosure(lua_state, Some(function.func), 0);
+        lua::lua_setfield(lua_state, -2, function.name.as_ptr() as _);
+    }
 
     lua::lua_newtable(lua_state);
-    lua::lua_getfield(lua_state, lua::LUA_REGISTRYINDEX, meta_name.as_ptr() as _);
+    lua::lua_rotate(lua_state, -2, 1);
     lua::lua_setmetatable(lua_state, -2);
 
-    let global_table = lua::bindings::index2addr(lua_state, lua::LUA_REGISTRYINDEX);
-    let table = (*global_table).value.ptr;
-    let value = if *(table as *mut u32).add(3) < 2 {
-        todo!()
-    } else {
-        (*(table as *mut *mut lua::bindings::TValue).add(2)).add(1)
-    };
-    lua::bindings::auxsetstr(lua_state, value, real_name.as_ptr() as _);
+    lua::lua_rawgeti(lua_state, lua::LUA_REGISTRYINDEX, lua::LUA_RIDX_GLOBALS);
+    if lua::lua_type(lua_state, -1) != lua::LUA_TTABLE {
+        log::error!("Failed to register {name}, the Lua state has no globals table");
+        lua::lua_pop(lua_state, 2);
+        return;
+    }
+
+    lua::lua_rotate(lua_state, -2, 1);
+    lua::lua_setfield(lua_state, -2, real_name.as_ptr() as _);
+    lua::lua_pop(lua_state, 1);
 }
 
 #[skyline::hook(offset = 0x3373a78, inline)]
 unsafe fn add_to_key_context(ctx: &InlineCtx) {
     let lua_state: *mut lua::lua_State = *ctx.registers[19].x.as_ref() as _;
 
-    let registry = &[
-        lua::luaL_Reg {
-            name: "send_message\0".as_ptr() as _,
-            func: Some(send_message),
-        },
-        lua::luaL_Reg {
-            name: "print_panel_name\0".as_ptr() as _,
-            func: Some(print_panel_name),
-        },
-        lua::luaL_Reg {
-            name: "get_panel_alt_count\0".as_ptr() as _,
-            func: Some(get_panel_alt_count),
-        },
-        lua::luaL_Reg {
-            name: "get_alt_texture_index\0".as_ptr() as _,
-            func: Some(get_alt_texture_index),
-        },
-        lua::luaL_Reg {
-            name: "set_alts\0".as_ptr() as _,
-            func: Some(set_alts),
-        },
-        lua::luaL_Reg {
-            name: "write_alt_field_to_bgm_id\0".as_ptr() as _,
-            func: Some(write_alt_field_to_bgm_id),
-        },
-        lua::luaL_Reg {
-            name: std::ptr::null(),
-            func: None,
-        },
-    ];
-
-    push_new_singleton(lua_state, "Alts", registry);
+    for &(name, functions) in NAMESPACES {
+        push_new_singleton(lua_state, name, functions);
+    }
 }
 
 #[repr(C)]
@@ -298,9 +645,25 @@ struct StageEntry {
 
 #[skyline::hook(offset = 0x1b327a0)]
 unsafe fn is_valid_entrance_param(arg: u64, arg2: i32) -> bool {
+    // Asked for every preview, so only the first call of a stage select sees the scene change.
+    // Coming back from a match, so the stats it recorded can be saved
+    let previous = crate::scene::current();
+    if crate::scene::dispatch(crate::scene::SceneEvent::StageSelect) != previous
+        && previous.is_match()
+    {
+        crate::storage::save_pending();
+    }
+
     let mut manager = MANAGER.write();
 
     manager.current_singleton = NonNull::new(arg as _);
+    manager.ensure_databases();
+
+    // Asked for every preview when the stage select opens. Selections left over from the last
+    // one are dropped, scripts that restore an alt push it with select_alt afterwards
+    if let Ok(preview) = usize::try_from(arg2) {
+        manager.carousel.reset(preview);
+    }
 
     let vec = &mut *((arg + 0x168) as *mut resources::containers::CppVector<StageEntry>);
 
@@ -319,59 +682,6 @@ unsafe fn is_valid_entrance_param(arg: u64, arg2: i32) -> bool {
     call_original!(arg, arg2)
 }
 
-pub fn get_ui_hash_to_stage_hash(stage: &[u8]) -> BTreeMap<Hash40, Hash40> {
-    let mut reader = Cursor::new(stage);
-
-    let param_data = prc::read_stream(&mut reader).unwrap();
-
-    let (_, main_list) = &param_data.0[0];
-
-    let ParamKind::List(list) = main_list else {
-        unreachable!()
-    };
-
-    let mut map = BTreeMap::new();
-
-    for param in list.0.iter() {
-        let ParamKind::Struct(param) = param else {
-            continue;
-        };
-
-        let ui_stage_id = param.0.iter().find_map(|(k, v)| {
-            if Hash40(k.0) == Hash40::from("ui_stage_id") {
-                let ParamKind::Hash(v) = v else {
-                    return None;
-                };
-
-                Some(Hash40(v.0))
-            } else {
-                None
-            }
-        });
-
-        let stage_place_id = param.0.iter().find_map(|(k, v)| {
-            if Hash40(k.0) == Hash40::from("stage_place_id") {
-                let ParamKind::Hash(v) = v else {
-                    return None;
-                };
-
-                Some(Hash40(v.0))
-            } else {
-                None
-            }
-        });
-
-        match (ui_stage_id, stage_place_id) {
-            (Some(ui), Some(place)) => {
-                map.insert(ui, place);
-            }
-            _ => {}
-        }
-    }
-
-    map
-}
-
 #[skyline::hook(offset = 0x3359ad0)]
 unsafe fn replace_texture(state: *mut lua::lua_State) -> i32 {
     if lua::lua_isinteger(state, -1) == 1 {
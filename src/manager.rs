@@ -1,9 +1,20 @@
 use std::{collections::BTreeMap, ptr::NonNull};
 
 use locks::RwLock;
-use smash_arc::{FilePath, Hash40, HashToIndex};
-
-use crate::{lua, music_fix::MusicCache, utils::ConcatHash};
+use smash_arc::{ArcLookup, FilePath, Hash40, HashToIndex};
+
+use crate::{
+    carousel::Carousel,
+    config, database,
+    favorites::Favorites,
+    folders,
+    manifest::{self, AltManifest},
+    music_fix::MusicCache,
+    replay::ReplayIndex,
+    resources::types::FilesystemInfo,
+    stats::{PlayStats, StatsKey},
+    utils::ConcatHash,
+};
 
 pub static MANAGER: RwLock<AltManager> = RwLock::new(AltManager::new());
 
@@ -40,30 +51,8 @@ impl StageInfo {
     }
 }
 
-fn dlc_stages() -> [Hash40; 11] {
-    [
-        Hash40::from("jack_mementoes"),
-        Hash40::from("brave_altar"),
-        Hash40::from("buddy_spiral"),
-        Hash40::from("dolly_stadium"),
-        Hash40::from("fe_shrine"),
-        Hash40::from("tantan_spring"),
-        Hash40::from("pickel_world"),
-        Hash40::from("ff_cave"),
-        Hash40::from("xeno_alst"),
-        Hash40::from("demon_dojo"),
-        Hash40::from("trail_castle"),
-    ]
-}
-
 #[derive(Copy, Clone, Debug)]
 pub enum StageKind {
-    Training,
-    Battlefield,
-    SmallBattlefield,
-    BigBattlefield,
-    FinalDestination,
-
     Normal(Hash40),
     DLC(Hash40),
 }
@@ -71,38 +60,22 @@ pub enum StageKind {
 impl StageKind {
     pub fn as_hash(&self) -> Hash40 {
         match self {
-            Self::Training => Hash40::from("training"),
-            Self::Battlefield => Hash40::from("battlefield"),
-            Self::SmallBattlefield => Hash40::from("battlefield_s"),
-            Self::BigBattlefield => Hash40::from("battlefield_l"),
-            Self::FinalDestination => Hash40::from("end"),
             Self::Normal(normal) => *normal,
             Self::DLC(dlc) => *dlc,
         }
     }
-
-    pub fn as_ui_hash(&self) -> Hash40 {
-        match self {
-            Self::SmallBattlefield => Hash40::from("battlefields"),
-            Self::BigBattlefield => Hash40::from("battlefieldl"),
-            _ => self.as_hash(),
-        }
-    }
 }
 
 impl From<Hash40> for StageKind {
     fn from(value: Hash40) -> Self {
-        if value == Hash40::from("training") {
-            Self::Training
-        } else if value == Hash40::from("battlefield") {
-            Self::Battlefield
-        } else if value == Hash40::from("battlefield_s") {
-            Self::SmallBattlefield
-        } else if value == Hash40::from("battlefield_l") {
-            Self::BigBattlefield
-        } else if value == Hash40::from("end") {
-            Self::FinalDestination
-        } else if dlc_stages().contains(&value) {
+        let is_dlc = config::CONFIG
+            .read()
+            .ui
+            .dlc_stages
+            .iter()
+            .any(|stage| Hash40::from(stage.as_str()) == value);
+
+        if is_dlc {
             Self::DLC(value)
         } else {
             Self::Normal(value)
@@ -110,8 +83,112 @@ impl From<Hash40> for StageKind {
     }
 }
 
+fn ui_extension(alt_id: usize) -> String {
+    if alt_id == 0 {
+        String::from(".bntx")
+    } else {
+        format!("_s{alt_id:02}.bntx")
+    }
+}
+
+/// Picks the template for one of a stage's UI textures, falling back from the stage's own
+/// templates to the ones of its layout
+pub fn ui_template(
+    config: &config::UiConfig,
+    kind: StageKind,
+    get: fn(&config::UiPathTemplates) -> &String,
+) -> &str {
+    let name = kind.as_hash();
+
+    let layout = match kind {
+        StageKind::Normal(_) => &config.vanilla,
+        StageKind::DLC(_) => &config.dlc,
+    };
+
+    let template = config
+        .stages
+        .iter()
+        .find_map(|(stage, templates)| (Hash40::from(stage.as_str()) == name).then_some(templates))
+        .map(get)
+        .filter(|template| !template.is_empty());
+
+    template.unwrap_or_else(|| get(layout))
+}
+
+/// Builds the path of a UI texture as a string, for when the stage's folder name is known
+pub fn expand_ui_template_str(template: &str, name: &str, alt_id: usize) -> String {
+    template
+        .replace("{name}", name)
+        .replace("{alt}", &format!("{alt_id:02}"))
+        .replace("{ext}", &ui_extension(alt_id))
+}
+
+/// Builds a path hash out of a UI path template, see [`config::UiPathTemplates`]
+fn expand_ui_template(template: &str, name: Hash40, alt_id: usize) -> Hash40 {
+    let extension = ui_extension(alt_id);
+
+    // We only have the hash of the stage name, so the path is built by concatenating the
+    // hashes of each segment instead of formatting a string
+    let mut hash = Hash40(0);
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+
+        if start != 0 {
+            hash = hash.concat(&rest[..start]);
+        }
+
+        hash = match &rest[start + 1..end] {
+            "name" => hash.concat(name),
+            "alt" => hash.concat(format!("{alt_id:02}").as_str()),
+            "ext" => hash.concat(extension.as_str()),
+            other => {
+                log::warn!("Unknown placeholder '{{{other}}}' in UI path template '{template}'");
+                hash.concat(&rest[start..=end])
+            }
+        };
+
+        rest = &rest[end + 1..];
+    }
+
+    if !rest.is_empty() {
+        hash = hash.concat(rest);
+    }
+
+    hash
+}
+
+/// The stage UI textures an alt can provide, numbered like the game's `stage_N` folders
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UiVariant {
+    Icon = 0,
+    Panel = 1,
+    Normal = 2,
+    End = 3,
+    Battle = 4,
+}
+
+impl UiVariant {
+    pub fn from_index(index: usize) -> Option<Self> {
+        let variant = match index {
+            0 => Self::Icon,
+            1 => Self::Panel,
+            2 => Self::Normal,
+            3 => Self::End,
+            4 => Self::Battle,
+            _ => return None,
+        };
+
+        Some(variant)
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct UiPaths {
+    pub icon: Hash40,
+    pub panel: Hash40,
     pub normal: Hash40,
     pub battle: Hash40,
     pub end: Hash40,
@@ -119,63 +196,82 @@ pub struct UiPaths {
 
 impl UiPaths {
     pub fn new(value: StageKind, alt_id: usize) -> Self {
-        let extension = if alt_id == 0 {
-            Hash40::from(".bntx")
-        } else {
-            Hash40::from(format!("_s{alt_id:02}.bntx").as_str())
-        };
-
-        let normal = match value {
-            kind @ (StageKind::DLC(_) | StageKind::SmallBattlefield) => {
-                Hash40::from("ui/replace_patch/stage/stage_2/stage_2_")
-                    .concat(kind.as_ui_hash())
-                    .concat(extension)
-            }
-            kind => Hash40::from("ui/replace/stage/stage_2/stage_2_")
-                .concat(kind.as_ui_hash())
-                .concat(extension),
-        };
+        let config = config::CONFIG.read();
+        let name = value.as_hash();
 
-        let battle = match value {
-            StageKind::Training => normal,
-            StageKind::Battlefield | StageKind::BigBattlefield | StageKind::SmallBattlefield => {
-                Hash40::from("ui/replace/stage/stage_4/stage_4_battlefield").concat(extension)
-            }
-            StageKind::DLC(hash) => Hash40::from("ui/replace_patch/stage/stage_4/stage_4_")
-                .concat(hash)
-                .concat(extension),
-            other => Hash40::from("ui/replace/stage/stage_4/stage_4_")
-                .concat(other.as_hash())
-                .concat(extension),
-        };
-
-        let end = match value {
-            StageKind::Training | StageKind::FinalDestination => normal,
-            StageKind::Battlefield | StageKind::BigBattlefield | StageKind::SmallBattlefield => {
-                Hash40::from("ui/replace/stage/stage_3/stage_3_battlefield_")
-                    .concat(format!("s{alt_id:02}.bntx").as_str())
-            }
-            StageKind::DLC(hash) => Hash40::from("ui/replace_patch/stage/stage_3/stage_3_")
-                .concat(hash)
-                .concat(extension),
-            other => Hash40::from("ui/replace/stage/stage_3/stage_3_")
-                .concat(other.as_hash())
-                .concat(extension),
+        let expand = |get: fn(&config::UiPathTemplates) -> &String| {
+            expand_ui_template(ui_template(&config.ui, value, get), name, alt_id)
         };
 
         Self {
-            normal,
-            battle,
-            end,
+            icon: expand(|templates| &templates.icon),
+            panel: expand(|templates| &templates.panel),
+            normal: expand(|templates| &templates.normal),
+            battle: expand(|templates| &templates.battle),
+            end: expand(|templates| &templates.end),
+        }
+    }
+
+    pub fn get(&self, variant: UiVariant) -> Hash40 {
+        match variant {
+            UiVariant::Icon => self.icon,
+            UiVariant::Panel => self.panel,
+            UiVariant::Normal => self.normal,
+            UiVariant::End => self.end,
+            UiVariant::Battle => self.battle,
         }
     }
 }
 
+/// The file path index of a texture, if the game or a mod provides it
+pub fn texture_index(path: Hash40) -> Option<u32> {
+    let arc = FilesystemInfo::instance()?.arc();
+    arc.get_file_path_index_from_hash(path)
+        .ok()
+        .map(|index| index.0)
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct AltInfo {
     pub slot_value: usize,
     pub wifi_safe: bool,
     pub ui_paths: UiPaths,
+
+    /// Identifies the alt's content, 0 until it has been computed, see
+    /// [`crate::search::start_fingerprinting`]
+    pub fingerprint: u64,
+
+    /// The alt's entry in `alts.toml`, if it has one
+    pub manifest: Option<&'static AltManifest>,
+
+    /// The tags from the alt's manifest and the stage's policy
+    pub tags: &'static [String],
+}
+
+/// Collects the tags of an alt from its manifest and the stage's policy. Alts are only built
+/// once at boot, so the tags are leaked to keep [`AltInfo`] `Copy`
+pub fn alt_tags(
+    info: StageInfo,
+    slot: usize,
+    manifest: Option<&'static AltManifest>,
+) -> &'static [String] {
+    let mut tags: Vec<String> = manifest
+        .map(|manifest| manifest.tags.clone())
+        .unwrap_or_default();
+
+    if let Some(policy) = config::CONFIG.read().stage_policy(info.name) {
+        for (tag, slots) in policy.tags.iter() {
+            if slots.contains(&slot) && !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+    }
+
+    if tags.is_empty() {
+        return &[];
+    }
+
+    Box::leak(tags.into_boxed_slice())
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -196,6 +292,61 @@ impl Default for SelectedAltInfo {
     }
 }
 
+/// Marks an alt field that holds a slot. Older builds wrote the alt's position in the stage
+/// select list instead, which depends on the tag filter and policies of whoever picked it
+const ALT_FIELD_SLOT_FLAG: u64 = 0x8000;
+const ALT_FIELD_FORM_SHIFT: u64 = 13;
+const ALT_FIELD_FORM_MASK: u64 = 0x3;
+const ALT_FIELD_SLOT_MASK: u64 = 0x1FFF;
+
+/// The 16 bit field that the stage select packs into bits 40 to 56 of the BGM id. It travels
+/// with the match to the other players online and is saved in replays
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AltField {
+    /// The form is the stage select's form id, 0 being the normal form
+    Slot { form: usize, slot: usize },
+
+    /// Written by older builds for the normal form, resolved through the stage select list
+    Position(usize),
+}
+
+impl AltField {
+    pub fn decode(field: u64) -> Self {
+        if field & ALT_FIELD_SLOT_FLAG != 0 {
+            Self::Slot {
+                form: ((field >> ALT_FIELD_FORM_SHIFT) & ALT_FIELD_FORM_MASK) as usize,
+                slot: (field & ALT_FIELD_SLOT_MASK) as usize,
+            }
+        } else {
+            Self::Position(field as usize)
+        }
+    }
+
+    pub fn encode(self) -> u64 {
+        match self {
+            Self::Slot { form, slot } => {
+                ALT_FIELD_SLOT_FLAG
+                    | ((form as u64 & ALT_FIELD_FORM_MASK) << ALT_FIELD_FORM_SHIFT)
+                    | (slot as u64 & ALT_FIELD_SLOT_MASK)
+            }
+            Self::Position(index) => index as u64 & ALT_FIELD_SLOT_MASK,
+        }
+    }
+
+    /// The stage form folder the alt is loaded into
+    pub fn stage_info(self, stage: Hash40) -> StageInfo {
+        let normal_form = match self {
+            Self::Slot { form, .. } => form == 0,
+            Self::Position(_) => true,
+        };
+
+        StageInfo {
+            name: stage,
+            normal_form,
+        }
+    }
+}
+
 pub enum PlayableAlts {
     OneStage(SelectedAltInfo),
     TwoStages([SelectedAltInfo; 2]),
@@ -211,6 +362,23 @@ pub struct AltManager {
     pub alts: BTreeMap<StageInfo, Vec<AltInfo>>,
     pub selected_alts: Option<SelectedAlts>,
 
+    // The alt slot that should be applied to each stage form folder the next time it
+    // is loaded, keyed by the folder so that overlapping loads don't share an alt
+    pub pending_alts: BTreeMap<StageInfo, usize>,
+    pub last_alt: Option<usize>,
+
+    // The alt field of a match whose stage id isn't in ui_stage_db, it is resolved against
+    // the first stage form folder of the picked form that loads
+    pub unresolved_alt: Option<AltField>,
+
+    /// Only alts with this tag can be selected
+    pub tag_filter: Option<String>,
+
+    // The policy of every stage that has one and the selectable alts of every stage form,
+    // rebuilt whenever the alts or the tag filter change since the Lua reads them every frame
+    policies: BTreeMap<Hash40, config::StagePolicy>,
+    selectable: BTreeMap<StageInfo, Vec<AltInfo>>,
+
     pub backup_filepaths: BTreeMap<Hash40, u32>,
     pub backup_searchpaths: BTreeMap<Hash40, u32>,
 
@@ -218,12 +386,27 @@ pub struct AltManager {
     pub index_to_hash: BTreeMap<usize, Hash40>,
     pub ui_to_place: BTreeMap<Hash40, Hash40>,
 
+    // The name_id of each place in ui_stage_db, so scripts can put a stage's own name back
+    pub place_name_ids: BTreeMap<Hash40, String>,
+
+    // Keyed by the stage id the game hands to a match, filled as matches load from the game's
+    // stage table once ui_stage_db confirms the place, see [`Self::place_for_stage_id`]
+    pub stage_id_to_place: BTreeMap<usize, Hash40>,
+
     pub current_singleton: Option<NonNull<()>>,
 
     pub music_cache: Option<MusicCache>,
 
+    pub stats: PlayStats,
+    pub favorites: Favorites,
+    pub replays: ReplayIndex,
+
+    // What each stage select preview is showing, owned here so every script cycles the same way
+    pub carousel: Carousel,
+
     pub stage_data: Option<Vec<u8>>,
     pub bgm_data: Option<Vec<u8>>,
+    pub databases_loaded: bool,
 }
 
 impl AltManager {
@@ -236,8 +419,25 @@ impl AltManager {
             return;
         };
 
-        self.music_cache = Some(MusicCache::new(stage, bgm));
-        self.ui_to_place = lua::get_ui_hash_to_stage_hash(stage);
+        match (database::parse_stage_db(stage), database::parse_bgm_db(bgm)) {
+            (Ok(stages), Ok(bgm)) => {
+                self.ui_to_place = stages
+                    .iter()
+                    .map(|stage| (Hash40(stage.ui_stage_id.0), Hash40(stage.stage_place_id.0)))
+                    .collect();
+                self.place_name_ids = stages
+                    .iter()
+                    .filter_map(|stage| {
+                        let name_id = stage.name_id.clone()?;
+                        Some((Hash40(stage.stage_place_id.0), name_id))
+                    })
+                    .collect();
+                self.music_cache =
+                    Some(MusicCache::new(&stages, bgm, &config::CONFIG.read().music));
+            }
+            (Err(e), _) => log::error!("Failed to parse ui_stage_db: {e}"),
+            (_, Err(e)) => log::error!("Failed to parse ui_bgm_db: {e}"),
+        }
 
         self.stage_data = None;
         self.bgm_data = None;
@@ -247,44 +447,354 @@ impl AltManager {
         Self {
             alts: BTreeMap::new(),
             selected_alts: None,
+            pending_alts: BTreeMap::new(),
+            last_alt: None,
+            unresolved_alt: None,
+            tag_filter: None,
+            policies: BTreeMap::new(),
+            selectable: BTreeMap::new(),
             backup_filepaths: BTreeMap::new(),
             backup_searchpaths: BTreeMap::new(),
             index_to_hash: BTreeMap::new(),
             ui_to_place: BTreeMap::new(),
+            place_name_ids: BTreeMap::new(),
+            stage_id_to_place: BTreeMap::new(),
             current_singleton: None,
             music_cache: None,
+            stats: PlayStats::new(),
+            favorites: Favorites::new(),
+            replays: ReplayIndex::new(),
+            carousel: Carousel::new(),
             stage_data: None,
             bgm_data: None,
+            databases_loaded: false,
+        }
+    }
+
+    /// Parses the stage and BGM databases once the game has loaded both of them
+    pub fn ensure_databases(&mut self) {
+        if self.databases_loaded {
+            return;
+        }
+
+        if self.stage_data.is_none() {
+            self.stage_data = database::read_loaded_file(database::STAGE_DB_PATH);
+        }
+
+        if self.bgm_data.is_none() {
+            self.bgm_data = database::read_loaded_file(database::BGM_DB_PATH);
+        }
+
+        if self.stage_data.is_some() && self.bgm_data.is_some() {
+            self.databases_loaded = true;
+            self.try_create_singleton_backups();
         }
     }
 
-    pub fn set_stage_data(&mut self, stage: &[u8]) {
-        self.stage_data = Some(stage.to_vec());
-        self.try_create_singleton_backups();
+    /// The place of the stage a match loads. Stage ids index the game's stage table rather than
+    /// ui_stage_db, so the place is read from the table and only trusted if a ui_stage_db row
+    /// has it. Until the databases are parsed the table is used as is
+    pub fn place_for_stage_id(&mut self, stage_id: usize) -> Option<Hash40> {
+        if let Some(place) = self.stage_id_to_place.get(&stage_id) {
+            return Some(*place);
+        }
+
+        let place = unsafe { database::game_stage_place(stage_id) };
+        let place = Hash40(place.0);
+
+        if self.ui_to_place.is_empty() {
+            return Some(place);
+        }
+
+        if !self.is_known_place(place) {
+            log::warn!(
+                "Stage id {stage_id:#x} reads as place {:#x}, which no ui_stage_db entry has",
+                place.0
+            );
+            return None;
+        }
+
+        self.stage_id_to_place.insert(stage_id, place);
+        Some(place)
     }
 
-    pub fn set_bgm_data(&mut self, bgm: &[u8]) {
-        self.bgm_data = Some(bgm.to_vec());
-        self.try_create_singleton_backups();
+    /// Whether a ui_stage_db entry has the place
+    fn is_known_place(&self, place: Hash40) -> bool {
+        self.ui_to_place.values().any(|known| *known == place)
     }
 
     pub fn add_alt(&mut self, stage_info: StageInfo, alt: usize, kind: StageKind) {
+        let manifest = manifest::find_for_folder(stage_info, folders::folder_name(stage_info, alt));
         self.alts.entry(stage_info).or_default().push(AltInfo {
             slot_value: alt,
             wifi_safe: true,
             ui_paths: UiPaths::new(kind, alt),
+            fingerprint: 0,
+            manifest,
+            tags: alt_tags(stage_info, alt, manifest),
         });
     }
 
-    pub fn nth_alt(&self, info: StageInfo, index: usize) -> Option<AltInfo> {
-        if index == 0 {
-            return None;
+    /// Installs the alts that were found at boot
+    pub fn set_installed_alts(&mut self, alts: BTreeMap<StageInfo, Vec<AltInfo>>) {
+        self.alts = alts;
+        self.rebuild_selectable();
+    }
+
+    pub fn set_fingerprint(&mut self, info: StageInfo, slot: usize, fingerprint: u64) {
+        let alts = self.alts.get_mut(&info).into_iter().flatten();
+        let selectable = self.selectable.get_mut(&info).into_iter().flatten();
+
+        for alt in alts.chain(selectable) {
+            if alt.slot_value == slot {
+                alt.fingerprint = fingerprint;
+            }
         }
+    }
 
-        self.alts
+    fn rebuild_selectable(&mut self) {
+        self.policies = config::CONFIG
+            .read()
+            .stages
+            .iter()
+            .map(|(name, policy)| (Hash40::from(name.as_str()), policy.clone()))
+            .collect();
+
+        let default_policy = config::StagePolicy::default();
+
+        self.selectable = self
+            .alts
+            .iter()
+            .map(|(info, alts)| {
+                let policy = self.policies.get(&info.name).unwrap_or(&default_policy);
+
+                let mut selectable: Vec<_> = alts
+                    .iter()
+                    .filter(|alt| !policy.hidden.contains(&alt.slot_value))
+                    .filter(|alt| match self.tag_filter.as_ref() {
+                        Some(tag) => alt.tags.contains(tag),
+                        None => true,
+                    })
+                    .copied()
+                    .collect();
+
+                // The sort is stable, so alts that aren't in the order keep their slot order
+                selectable.sort_by_key(|alt| {
+                    policy
+                        .order
+                        .iter()
+                        .position(|slot| *slot == alt.slot_value)
+                        .unwrap_or(usize::MAX)
+                });
+
+                (*info, selectable)
+            })
+            .collect();
+    }
+
+    fn policy(&self, info: StageInfo) -> Option<&config::StagePolicy> {
+        self.policies.get(&info.name)
+    }
+
+    /// The alts of a stage form that can be selected, in the order they are listed
+    pub fn selectable_alts(&self, info: StageInfo) -> &[AltInfo] {
+        self.selectable
             .get(&info)
-            .and_then(|list| list.get(index - 1))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Restricts the selectable alts to the ones with a tag. A tag that no installed alt has
+    /// is ignored so that an untagged install doesn't lose every alt. The filter is cleared
+    /// when leaving local play, and online matches aren't affected by it since the alt field
+    /// holds slots
+    pub fn set_tag_filter(&mut self, tag: Option<String>) {
+        let tag = tag.filter(|tag| {
+            let known = self
+                .alts
+                .values()
+                .flatten()
+                .any(|alt| alt.tags.contains(tag));
+            if !known {
+                log::warn!("No installed alt is tagged '{tag}', showing every alt");
+            }
+
+            known
+        });
+
+        if self.tag_filter == tag {
+            return;
+        }
+
+        self.tag_filter = tag;
+        self.rebuild_selectable();
+    }
+
+    /// Picks a random position in the stage select list of a stage form, 0 being the base
+    /// stage. With `least_played` only the least played alts are picked from
+    pub fn random_alt_index(&mut self, info: StageInfo, least_played: bool) -> usize {
+        use rand::prelude::*;
+
+        let slots: Vec<usize> = std::iter::once(0)
+            .chain(self.selectable_alts(info).iter().map(|alt| alt.slot_value))
+            .collect();
+
+        let plays: Vec<u32> = slots
+            .iter()
+            .map(|slot| {
+                self.stats
+                    .get(StatsKey {
+                        stage: info.name,
+                        normal_form: info.normal_form,
+                        slot: *slot,
+                    })
+                    .plays
+            })
+            .collect();
+
+        let fewest = plays.iter().copied().min().unwrap_or_default();
+
+        let candidates: Vec<usize> = (0..slots.len())
+            .filter(|index| !least_played || plays[*index] == fewest)
+            .collect();
+
+        candidates
+            .choose(&mut rand::thread_rng())
             .copied()
+            .unwrap_or_default()
+    }
+
+    /// The positions of the favorite alts in the stage select list of a stage form
+    pub fn favorite_indices(&mut self, info: StageInfo) -> Vec<usize> {
+        let alts = self
+            .selectable
+            .get(&info)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        alts.iter()
+            .enumerate()
+            .filter(|(_, alt)| self.favorites.contains(info, alt.slot_value))
+            .map(|(index, _)| index + 1)
+            .collect()
+    }
+
+    /// Toggles whether the alt at a position of the stage select list is a favorite,
+    /// returning whether it now is one. The base stage can't be a favorite
+    pub fn toggle_favorite(&mut self, info: StageInfo, index: usize) -> bool {
+        let Some(alt) = index
+            .checked_sub(1)
+            .and_then(|index| self.selectable_alts(info).get(index).copied())
+        else {
+            return false;
+        };
+
+        self.favorites.toggle(info, alt.slot_value)
+    }
+
+    /// The position of the next favorite after `current` in the given direction, wrapping
+    /// around the list. Stays on `current` if the stage form has no favorites
+    pub fn next_favorite(&mut self, info: StageInfo, current: usize, forward: bool) -> usize {
+        let favorites = self.favorite_indices(info);
+
+        let next = if forward {
+            favorites
+                .iter()
+                .find(|index| **index > current)
+                .or(favorites.first())
+        } else {
+            favorites
+                .iter()
+                .rev()
+                .find(|index| **index < current)
+                .or(favorites.last())
+        };
+
+        next.copied().unwrap_or(current)
+    }
+
+    /// The texture the stage select preview shows for the alt at a position of the list.
+    /// Alts without their own texture (or a generated placeholder) show the base stage
+    pub fn preview_texture_index(
+        &self,
+        stage: Hash40,
+        form_id: usize,
+        alt_id: usize,
+    ) -> Option<u32> {
+        let base_paths = UiPaths::new(StageKind::from(stage), 0);
+
+        let alt = self.nth_alt(
+            StageInfo {
+                name: stage,
+                normal_form: form_id == 0,
+            },
+            alt_id,
+        );
+
+        let paths = match alt {
+            Some(alt) => alt.ui_paths,
+            None if alt_id == 0 => base_paths,
+            None => return None,
+        };
+
+        let variant = match form_id {
+            1 => UiVariant::Battle,
+            2 => UiVariant::End,
+            _ => UiVariant::Normal,
+        };
+
+        let path = paths.get(variant);
+        let index = texture_index(path).or_else(|| texture_index(base_paths.get(variant)));
+        if index.is_none() {
+            log::warn!("Could not get file path index for {}", path.pretty());
+        }
+
+        index
+    }
+
+    /// Whether the stage's policy always plays the same alt
+    pub fn is_locked(&self, info: StageInfo) -> bool {
+        self.policy(info)
+            .is_some_and(|policy| policy.lock.is_some())
+    }
+
+    /// How many alts the player can cycle through, locked stages have none
+    pub fn selectable_alt_count(&self, info: StageInfo) -> usize {
+        if self.is_locked(info) {
+            return 0;
+        }
+
+        self.selectable_alts(info).len()
+    }
+
+    fn find_policy_alt(&self, info: StageInfo, slot: usize) -> Option<AltInfo> {
+        let alt = self.find_alt_by_slot(info, slot);
+        if alt.is_none() {
+            log::warn!(
+                "The policy of {} refers to alt {slot}, which is not installed",
+                crate::utils::string_for_hash(info.name)
+            );
+        }
+
+        alt
+    }
+
+    /// Resolves the alt at a position of the stage select list, where 0 is the stage's
+    /// default (the base stage unless the policy sets one)
+    pub fn nth_alt(&self, info: StageInfo, index: usize) -> Option<AltInfo> {
+        let policy = self.policy(info);
+
+        if let Some(slot) = policy.and_then(|policy| policy.lock) {
+            return self.find_policy_alt(info, slot);
+        }
+
+        if index == 0 {
+            return policy
+                .and_then(|policy| policy.default)
+                .and_then(|slot| self.find_policy_alt(info, slot));
+        }
+
+        self.selectable_alts(info).get(index - 1).copied()
     }
 
     pub fn set_alts(
@@ -327,14 +837,107 @@ impl AltManager {
         }
     }
 
-    pub fn fetch_alt_for_stage(&self, stage: Hash40, alt: usize) -> Option<usize> {
-        self.nth_alt(
-            StageInfo {
-                name: stage,
-                normal_form: true,
-            },
-            alt,
-        )
-        .map(|alt| alt.slot_value)
+    /// Sets the alt that the stage form folder should use on its next load, a `None` alt
+    /// means the folder is loading the base stage
+    pub fn set_pending_alt(&mut self, folder: StageInfo, alt: Option<usize>) {
+        match alt {
+            Some(alt) => {
+                self.pending_alts.insert(folder, alt);
+            }
+            None => {
+                self.pending_alts.remove(&folder);
+            }
+        }
+
+        self.last_alt = alt;
+        self.unresolved_alt = None;
+    }
+
+    /// Sets the alt of a match whose stage couldn't be looked up, see [`Self::pending_alt`]
+    pub fn set_unresolved_alt(&mut self, field: AltField) {
+        self.unresolved_alt = Some(field);
+    }
+
+    pub fn pending_alt(&mut self, folder: StageInfo) -> Option<usize> {
+        let unresolved = self
+            .unresolved_alt
+            .filter(|field| field.stage_info(folder.name) == folder);
+
+        if let Some(field) = unresolved {
+            let alt = self.resolve_alt_field(folder.name, field);
+            self.set_pending_alt(folder, alt);
+        }
+
+        self.pending_alts.get(&folder).copied()
+    }
+
+    pub fn find_alt_by_slot(&self, info: StageInfo, slot: usize) -> Option<AltInfo> {
+        self.alts
+            .get(&info)
+            .and_then(|list| list.iter().find(|alt| alt.slot_value == slot))
+            .copied()
+    }
+
+    /// Resolves the alt field of a BGM id to the alt slot of a stage form, `None` being the
+    /// base stage
+    pub fn resolve_alt_field(&self, stage: Hash40, field: AltField) -> Option<usize> {
+        let info = field.stage_info(stage);
+
+        match field {
+            AltField::Slot { slot: 0, .. } => None,
+            AltField::Slot { slot, .. } => {
+                if self.find_alt_by_slot(info, slot).is_none() {
+                    log::warn!(
+                        "Alt {slot} of {} was picked, but it is not installed",
+                        crate::utils::string_for_hash(stage)
+                    );
+                    return None;
+                }
+
+                Some(slot)
+            }
+            AltField::Position(index) => self.nth_alt(info, index).map(|alt| alt.slot_value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alt_field_round_trips() {
+        for form in 0..3 {
+            for slot in [0, 1, 42, 999, ALT_FIELD_SLOT_MASK as usize] {
+                let field = AltField::Slot { form, slot };
+                assert_eq!(AltField::decode(field.encode()), field);
+            }
+        }
+    }
+
+    #[test]
+    fn alt_field_reads_old_positions() {
+        assert_eq!(AltField::decode(0), AltField::Position(0));
+        assert_eq!(AltField::decode(7), AltField::Position(7));
+        assert!(AltField::Position(7).stage_info(Hash40(1)).normal_form);
+    }
+
+    #[test]
+    fn only_places_from_the_database_are_known() {
+        let mut mgr = AltManager::new();
+        mgr.ui_to_place.insert(
+            Hash40::from("ui_stage_battle_field"),
+            Hash40::from("battlefield"),
+        );
+
+        assert!(mgr.is_known_place(Hash40::from("battlefield")));
+        assert!(!mgr.is_known_place(Hash40::from("ui_stage_battle_field")));
+        assert!(!mgr.is_known_place(Hash40(0xdead_beef)));
+    }
+
+    #[test]
+    fn alt_field_keeps_the_form() {
+        let field = AltField::decode(AltField::Slot { form: 1, slot: 5 }.encode());
+        assert!(!field.stage_info(Hash40(1)).normal_form);
     }
 }
@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+
+use locks::RwLock;
+use serde::Deserialize;
+use smash_arc::Hash40;
+
+use crate::{folders, manager::StageInfo};
+
+const MANIFEST_PATH: &str = "sd:/ultimate/stage-alts/alts.toml";
+
+/// Every manifest entry, leaked so that alts can keep a `'static` reference to theirs
+pub static MANIFESTS: RwLock<&'static [AltManifest]> = RwLock::new(&[]);
+
+/// Describes a single alt, loaded from `alts.toml`
+#[derive(Deserialize, Clone, Debug)]
+pub struct AltManifest {
+    /// The stage's folder name, e.g. `battlefield`
+    pub stage: String,
+
+    /// `normal` or `battle`
+    #[serde(default = "default_form")]
+    pub form: String,
+
+    pub slot: usize,
+
+    /// The alt's folder inside the stage's folder when it isn't named `<form>_sXX`,
+    /// e.g. `normal_ruins`
+    pub folder: Option<String>,
+
+    /// The name shown for the alt when there's no translation for the current language
+    pub name: Option<String>,
+
+    pub author: Option<String>,
+
+    /// Translated names, keyed by region code (e.g. `us_en`, `eu_fr`)
+    #[serde(default)]
+    pub names: BTreeMap<String, String>,
+
+    /// Free form tags like `competitive` or `hazardless` that the stage select can filter by
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+fn default_form() -> String {
+    String::from("normal")
+}
+
+#[derive(Deserialize, Default)]
+struct ManifestFile {
+    #[serde(default)]
+    alt: Vec<AltManifest>,
+}
+
+impl AltManifest {
+    pub fn stage_info(&self) -> Option<StageInfo> {
+        let normal_form = match self.form.as_str() {
+            "normal" => true,
+            "battle" => false,
+            other => {
+                log::warn!("Unknown form '{other}' in the manifest for {}", self.stage);
+                return None;
+            }
+        };
+
+        Some(StageInfo {
+            name: Hash40::from(self.stage.as_str()),
+            normal_form,
+        })
+    }
+
+    /// The name to show for the alt in the given language, if it has one
+    pub fn display_name(&self, language: &str) -> Option<&str> {
+        self.names
+            .get(language)
+            .or(self.name.as_ref())
+            .map(String::as_str)
+    }
+}
+
+pub fn load() {
+    let manifests = match std::fs::read_to_string(MANIFEST_PATH) {
+        Ok(data) => match toml::from_str::<ManifestFile>(&data) {
+            Ok(file) => file.alt,
+            Err(e) => {
+                log::error!("Failed to parse alt manifests: {e}");
+                vec![]
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(e) => {
+            log::error!("Failed to read alt manifests: {e:?}");
+            vec![]
+        }
+    };
+
+    log::info!("Loaded {} alt manifests", manifests.len());
+
+    *MANIFESTS.write() = Box::leak(manifests.into_boxed_slice());
+}
+
+/// Finds the manifest of an alt by its folder rather than its slot, so that it still works for alts
+/// that were remapped to another slot
+pub fn find_for_folder(info: StageInfo, folder: Hash40) -> Option<&'static AltManifest> {
+    let manifests: &'static [AltManifest] = *MANIFESTS.read();
+    manifests.iter().find(|manifest| {
+        let name = match manifest.folder.as_deref() {
+            Some(name) => Hash40::from(name),
+            None => Hash40::from(folders::numbered_name(info.normal_form, manifest.slot).as_str()),
+        };
+
+        name == folder && manifest.stage_info() == Some(info)
+    })
+}
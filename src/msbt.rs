@@ -0,0 +1,565 @@
+//! Reads and writes `MsgStdBn` message files. Only the little endian UTF-16 flavour that the
+//! game ships is supported. Sections we don't need to understand are kept as raw bytes so that
+//! they survive a round trip unchanged
+
+use std::fmt::Display;
+
+const MAGIC: &[u8; 8] = b"MsgStdBn";
+const HEADER_SIZE: usize = 0x20;
+const SECTION_HEADER_SIZE: usize = 0x10;
+const SECTION_ALIGNMENT: usize = 0x10;
+const PADDING_BYTE: u8 = 0xAB;
+const ENCODING_UTF16: u8 = 1;
+
+#[derive(Debug)]
+pub enum MsbtError {
+    /// The data ended before a structure it claims to contain
+    Truncated,
+
+    /// The file does not start with `MsgStdBn`
+    BadMagic,
+
+    /// The file is big endian
+    BigEndian,
+
+    /// The strings are not UTF-16
+    UnsupportedEncoding(u8),
+
+    /// A required section is missing
+    MissingSection(&'static str),
+
+    /// The label is too long or not ASCII
+    InvalidLabel(String),
+
+    /// The attribute section stores strings after its table, adding to the table would
+    /// shift them out from under their offsets
+    UnsupportedAttributes,
+}
+
+impl Display for MsbtError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "unexpected end of data"),
+            Self::BadMagic => write!(f, "not an msbt file"),
+            Self::BigEndian => write!(f, "big endian msbt files are not supported"),
+            Self::UnsupportedEncoding(encoding) => {
+                write!(f, "unsupported string encoding {encoding}")
+            }
+            Self::MissingSection(magic) => write!(f, "missing {magic} section"),
+            Self::InvalidLabel(label) => write!(f, "invalid label '{label}'"),
+            Self::UnsupportedAttributes => {
+                write!(f, "attribute sections with string data are not supported")
+            }
+        }
+    }
+}
+
+fn read_u8(data: &[u8], offset: usize) -> Result<u8, MsbtError> {
+    data.get(offset).copied().ok_or(MsbtError::Truncated)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, MsbtError> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or(MsbtError::Truncated)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, MsbtError> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or(MsbtError::Truncated)
+}
+
+fn read_slice(data: &[u8], start: usize, end: usize) -> Result<&[u8], MsbtError> {
+    if start > end {
+        return Err(MsbtError::Truncated);
+    }
+
+    data.get(start..end).ok_or(MsbtError::Truncated)
+}
+
+/// The hash the game uses to bucket labels in `LBL1`
+pub fn label_hash(label: &str, slot_count: u32) -> u32 {
+    label.bytes().fold(0u32, |hash, byte| {
+        hash.wrapping_mul(0x492).wrapping_add(byte as u32)
+    }) % slot_count.max(1)
+}
+
+/// Encodes a message as null terminated UTF-16
+pub fn encode_message(text: &str) -> Vec<u8> {
+    text.encode_utf16()
+        .chain(std::iter::once(0))
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+/// Decodes a null terminated UTF-16 message, control codes are kept as is
+pub fn decode_message(data: &[u8]) -> String {
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .take_while(|unit| *unit != 0)
+        .collect();
+
+    String::from_utf16_lossy(&units)
+}
+
+enum Section {
+    Labels,
+    Attributes,
+    Texts,
+
+    /// Style indices, one `u32` per message
+    Styles,
+    Raw([u8; 4], Vec<u8>),
+}
+
+pub struct Msbt {
+    header: [u8; HEADER_SIZE],
+    sections: Vec<Section>,
+
+    /// How many hash buckets `LBL1` has
+    slot_count: u32,
+
+    /// Every label and the index of the message it names
+    labels: Vec<(String, u32)>,
+
+    attribute_size: u32,
+    attributes: Vec<u8>,
+    attribute_strings: Vec<u8>,
+
+    styles: Vec<u8>,
+
+    /// The raw UTF-16 data of every message, including its terminator
+    messages: Vec<Vec<u8>>,
+}
+
+impl Msbt {
+    pub fn parse(data: &[u8]) -> Result<Self, MsbtError> {
+        let header: [u8; HEADER_SIZE] = read_slice(data, 0, HEADER_SIZE)?
+            .try_into()
+            .map_err(|_| MsbtError::Truncated)?;
+
+        if &header[..8] != MAGIC {
+            return Err(MsbtError::BadMagic);
+        }
+
+        if header[8..10] != [0xFF, 0xFE] {
+            return Err(MsbtError::BigEndian);
+        }
+
+        let encoding = read_u8(&header, 0xC)?;
+        if encoding != ENCODING_UTF16 {
+            return Err(MsbtError::UnsupportedEncoding(encoding));
+        }
+
+        let section_count = read_u16(&header, 0xE)?;
+
+        let mut msbt = Self {
+            header,
+            sections: Vec::with_capacity(section_count as usize),
+            slot_count: 0,
+            labels: vec![],
+            attribute_size: 0,
+            attributes: vec![],
+            attribute_strings: vec![],
+            styles: vec![],
+            messages: vec![],
+        };
+
+        let mut has_labels = false;
+        let mut has_texts = false;
+
+        let mut offset = HEADER_SIZE;
+        for _ in 0..section_count {
+            let magic: [u8; 4] = read_slice(data, offset, offset + 4)?
+                .try_into()
+                .map_err(|_| MsbtError::Truncated)?;
+            let size = read_u32(data, offset + 4)? as usize;
+
+            let start = offset + SECTION_HEADER_SIZE;
+            let body = read_slice(data, start, start + size)?;
+
+            let section = match &magic {
+                b"LBL1" => {
+                    msbt.parse_labels(body)?;
+                    has_labels = true;
+                    Section::Labels
+                }
+                b"ATR1" => {
+                    msbt.parse_attributes(body)?;
+                    Section::Attributes
+                }
+                b"TXT2" => {
+                    msbt.parse_texts(body)?;
+                    has_texts = true;
+                    Section::Texts
+                }
+                b"TSY1" => {
+                    msbt.styles = body.to_vec();
+                    Section::Styles
+                }
+                _ => Section::Raw(magic, body.to_vec()),
+            };
+
+            msbt.sections.push(section);
+
+            offset = (start + size).next_multiple_of(SECTION_ALIGNMENT);
+        }
+
+        if !has_labels {
+            return Err(MsbtError::MissingSection("LBL1"));
+        }
+
+        if !has_texts {
+            return Err(MsbtError::MissingSection("TXT2"));
+        }
+
+        Ok(msbt)
+    }
+
+    fn parse_labels(&mut self, body: &[u8]) -> Result<(), MsbtError> {
+        self.slot_count = read_u32(body, 0)?;
+
+        for slot in 0..self.slot_count as usize {
+            let count = read_u32(body, 4 + slot * 8)?;
+            let mut offset = read_u32(body, 8 + slot * 8)? as usize;
+
+            for _ in 0..count {
+                let len = read_u8(body, offset)? as usize;
+                let name = read_slice(body, offset + 1, offset + 1 + len)?;
+                let index = read_u32(body, offset + 1 + len)?;
+
+                self.labels
+                    .push((String::from_utf8_lossy(name).into_owned(), index));
+
+                offset += 1 + len + 4;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_attributes(&mut self, body: &[u8]) -> Result<(), MsbtError> {
+        let count = read_u32(body, 0)? as usize;
+        self.attribute_size = read_u32(body, 4)?;
+
+        let table_end = 8 + count * self.attribute_size as usize;
+        self.attributes = read_slice(body, 8, table_end)?.to_vec();
+        self.attribute_strings = body[table_end..].to_vec();
+
+        Ok(())
+    }
+
+    fn parse_texts(&mut self, body: &[u8]) -> Result<(), MsbtError> {
+        let count = read_u32(body, 0)? as usize;
+
+        let offsets = (0..count)
+            .map(|index| read_u32(body, 4 + index * 4).map(|offset| offset as usize))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (index, start) in offsets.iter().enumerate() {
+            let end = offsets.get(index + 1).copied().unwrap_or(body.len());
+            self.messages.push(read_slice(body, *start, end)?.to_vec());
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, label: &str) -> Option<String> {
+        let (_, index) = self.labels.iter().find(|(name, _)| name == label)?;
+        self.messages
+            .get(*index as usize)
+            .map(|message| decode_message(message))
+    }
+
+    /// Replaces the message under `label`, or adds a new message if there isn't one yet
+    pub fn set(&mut self, label: &str, text: &str) -> Result<(), MsbtError> {
+        if !label.is_ascii() || label.is_empty() || label.len() > u8::MAX as usize {
+            return Err(MsbtError::InvalidLabel(label.to_string()));
+        }
+
+        if let Some((_, index)) = self.labels.iter().find(|(name, _)| name == label) {
+            if let Some(message) = self.messages.get_mut(*index as usize) {
+                *message = encode_message(text);
+                return Ok(());
+            }
+        }
+
+        let has_attributes = self
+            .sections
+            .iter()
+            .any(|section| matches!(section, Section::Attributes));
+
+        if has_attributes && self.attribute_size != 0 && !self.attribute_strings.is_empty() {
+            return Err(MsbtError::UnsupportedAttributes);
+        }
+
+        let index = self.messages.len() as u32;
+        self.messages.push(encode_message(text));
+        self.labels.push((label.to_string(), index));
+
+        self.attributes
+            .resize(self.attributes.len() + self.attribute_size as usize, 0);
+
+        if self
+            .sections
+            .iter()
+            .any(|section| matches!(section, Section::Styles))
+        {
+            self.styles.extend_from_slice(&0u32.to_le_bytes());
+        }
+
+        Ok(())
+    }
+
+    fn write_labels(&self) -> Vec<u8> {
+        let slot_count = self.slot_count.max(1);
+
+        let mut slots = vec![vec![]; slot_count as usize];
+        for (label, index) in self.labels.iter() {
+            slots[label_hash(label, slot_count) as usize].push((label, *index));
+        }
+
+        let mut table = Vec::with_capacity(4 + slots.len() * 8);
+        let mut entries = vec![];
+
+        table.extend_from_slice(&slot_count.to_le_bytes());
+
+        let entries_start = 4 + slots.len() * 8;
+        for slot in slots.iter() {
+            table.extend_from_slice(&(slot.len() as u32).to_le_bytes());
+            table.extend_from_slice(&((entries_start + entries.len()) as u32).to_le_bytes());
+
+            for (label, index) in slot.iter() {
+                entries.push(label.len() as u8);
+                entries.extend_from_slice(label.as_bytes());
+                entries.extend_from_slice(&index.to_le_bytes());
+            }
+        }
+
+        table.extend(entries);
+        table
+    }
+
+    fn write_attributes(&self) -> Vec<u8> {
+        let count = self.messages.len() as u32;
+
+        let mut body = Vec::with_capacity(8 + self.attributes.len());
+        body.extend_from_slice(&count.to_le_bytes());
+        body.extend_from_slice(&self.attribute_size.to_le_bytes());
+        body.extend_from_slice(&self.attributes);
+        body.extend_from_slice(&self.attribute_strings);
+        body
+    }
+
+    fn write_texts(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&(self.messages.len() as u32).to_le_bytes());
+
+        let mut offset = 4 + self.messages.len() * 4;
+        for message in self.messages.iter() {
+            body.extend_from_slice(&(offset as u32).to_le_bytes());
+            offset += message.len();
+        }
+
+        for message in self.messages.iter() {
+            body.extend_from_slice(message);
+        }
+
+        body
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = self.header.to_vec();
+
+        for section in self.sections.iter() {
+            let (magic, body) = match section {
+                Section::Labels => (*b"LBL1", self.write_labels()),
+                Section::Attributes => (*b"ATR1", self.write_attributes()),
+                Section::Texts => (*b"TXT2", self.write_texts()),
+                Section::Styles => (*b"TSY1", self.styles.clone()),
+                Section::Raw(magic, body) => (*magic, body.clone()),
+            };
+
+            data.extend_from_slice(&magic);
+            data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            data.extend_from_slice(&[0; 8]);
+            data.extend(body);
+
+            let padded = data.len().next_multiple_of(SECTION_ALIGNMENT);
+            data.resize(padded, PADDING_BYTE);
+        }
+
+        let section_count = self.sections.len() as u16;
+        let file_size = data.len() as u32;
+        data[0xE..0x10].copy_from_slice(&section_count.to_le_bytes());
+        data[0x12..0x16].copy_from_slice(&file_size.to_le_bytes());
+
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(magic: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = magic.to_vec();
+        data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(body);
+        data.resize(data.len().next_multiple_of(SECTION_ALIGNMENT), PADDING_BYTE);
+        data
+    }
+
+    /// Builds a file with the given messages the way the game lays them out, with one byte
+    /// of attributes per message
+    fn build(messages: &[(&str, &str)], attributes: &[u8]) -> Vec<u8> {
+        let slot_count = 3;
+
+        let mut slots = vec![vec![]; slot_count as usize];
+        for (index, (label, _)) in messages.iter().enumerate() {
+            slots[label_hash(label, slot_count) as usize].push((*label, index as u32));
+        }
+
+        let mut labels = slot_count.to_le_bytes().to_vec();
+        let mut entries = vec![];
+        for slot in slots.iter() {
+            labels.extend_from_slice(&(slot.len() as u32).to_le_bytes());
+            labels.extend_from_slice(&((4 + slots.len() * 8 + entries.len()) as u32).to_le_bytes());
+            for (label, index) in slot.iter() {
+                entries.push(label.len() as u8);
+                entries.extend_from_slice(label.as_bytes());
+                entries.extend_from_slice(&index.to_le_bytes());
+            }
+        }
+        labels.extend(entries);
+
+        let attributes = [
+            &(messages.len() as u32).to_le_bytes()[..],
+            &1u32.to_le_bytes(),
+            attributes,
+        ]
+        .concat();
+
+        let encoded: Vec<_> = messages
+            .iter()
+            .map(|(_, text)| encode_message(text))
+            .collect();
+        let mut texts = (messages.len() as u32).to_le_bytes().to_vec();
+        let mut offset = 4 + messages.len() * 4;
+        for message in encoded.iter() {
+            texts.extend_from_slice(&(offset as u32).to_le_bytes());
+            offset += message.len();
+        }
+        texts.extend(encoded.concat());
+
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&[0xFF, 0xFE, 0, 0, ENCODING_UTF16, 3]);
+        data.extend_from_slice(&3u16.to_le_bytes());
+        data.resize(HEADER_SIZE, 0);
+
+        data.extend(section(b"LBL1", &labels));
+        data.extend(section(b"ATR1", &attributes));
+        data.extend(section(b"TXT2", &texts));
+
+        let size = data.len() as u32;
+        data[0x12..0x16].copy_from_slice(&size.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn message_round_trip() {
+        let encoded = encode_message("Battlefield Ω");
+        assert_eq!(
+            encoded.len(),
+            ("Battlefield Ω".encode_utf16().count() + 1) * 2
+        );
+        assert_eq!(decode_message(&encoded), "Battlefield Ω");
+    }
+
+    #[test]
+    fn unchanged_file_round_trips() {
+        let data = build(
+            &[
+                ("stage_name_battlefield", "Battlefield"),
+                ("stage_name_end", "Final Destination"),
+            ],
+            &[1, 2],
+        );
+        let msbt = Msbt::parse(&data).unwrap();
+
+        assert_eq!(
+            msbt.get("stage_name_battlefield").as_deref(),
+            Some("Battlefield")
+        );
+        assert_eq!(
+            msbt.get("stage_name_end").as_deref(),
+            Some("Final Destination")
+        );
+        assert_eq!(msbt.get("stage_name_missing"), None);
+        assert_eq!(msbt.to_bytes(), data);
+    }
+
+    #[test]
+    fn set_replaces_and_adds_messages() {
+        let mut msbt =
+            Msbt::parse(&build(&[("stage_name_battlefield", "Battlefield")], &[7])).unwrap();
+
+        msbt.set("stage_name_battlefield", "Ruins").unwrap();
+        msbt.set("stage_name_battlefield_s01", "Sunset").unwrap();
+
+        // Added messages get zeroed attributes
+        let expected = build(
+            &[
+                ("stage_name_battlefield", "Ruins"),
+                ("stage_name_battlefield_s01", "Sunset"),
+            ],
+            &[7, 0],
+        );
+        assert_eq!(msbt.to_bytes(), expected);
+
+        let msbt = Msbt::parse(&expected).unwrap();
+        assert_eq!(msbt.get("stage_name_battlefield").as_deref(), Some("Ruins"));
+        assert_eq!(
+            msbt.get("stage_name_battlefield_s01").as_deref(),
+            Some("Sunset")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_labels() {
+        let mut msbt = Msbt::parse(&build(&[("label", "text")], &[0])).unwrap();
+
+        assert!(matches!(
+            msbt.set("", "text"),
+            Err(MsbtError::InvalidLabel(_))
+        ));
+        assert!(matches!(
+            msbt.set("ラベル", "text"),
+            Err(MsbtError::InvalidLabel(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let data = build(&[("label", "text")], &[0]);
+
+        let mut bad_magic = data.clone();
+        bad_magic[0] = b'X';
+        assert!(matches!(Msbt::parse(&bad_magic), Err(MsbtError::BadMagic)));
+
+        let mut big_endian = data.clone();
+        big_endian[8..10].copy_from_slice(&[0xFE, 0xFF]);
+        assert!(matches!(
+            Msbt::parse(&big_endian),
+            Err(MsbtError::BigEndian)
+        ));
+
+        assert!(matches!(
+            Msbt::parse(&data[..data.len() - 0x20]),
+            Err(MsbtError::Truncated)
+        ));
+    }
+}
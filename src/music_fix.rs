@@ -1,107 +1,135 @@
-use std::{
-    collections::{HashMap, HashSet},
-    path::Path,
-};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use hash40::{hash40, Hash40};
-use prc::{ParamKind, ParamStruct};
+
+use crate::{
+    config::{MusicConfig, MusicPolicy},
+    database::{BgmDatabase, PlaylistEntry, StageDbEntry, PLAYLIST_COLUMNS},
+};
+
+#[derive(Copy, Clone, Debug)]
+pub struct StagePlaylist {
+    pub series: Hash40,
+    pub setting: usize,
+}
 
 pub struct MusicCache {
     pub allowed_songs: HashSet<Hash40>,
-    pub song_by_series: HashMap<Hash40, Vec<Hash40>>,
-    pub stage_to_series: HashMap<Hash40, Hash40>,
+    pub song_by_series: HashMap<Hash40, Vec<PlaylistEntry>>,
+    pub stage_to_series: HashMap<Hash40, StagePlaylist>,
+    pub song_overrides: HashMap<Hash40, Vec<Hash40>>,
+    pub policy: MusicPolicy,
+    pub fallback_song: Hash40,
+    pub history: VecDeque<Hash40>,
+    pub history_length: usize,
 }
 
-fn prc_get(prc: &ParamStruct, key: Hash40) -> Option<&ParamKind> {
-    prc.0.iter().find_map(|(k, v)| (*k == key).then_some(v))
-}
+/// Picks a song using the weights, avoiding the songs in `history` unless they are the only
+/// ones that can be picked
+fn pick_weighted(candidates: &[(Hash40, u32)], history: &VecDeque<Hash40>) -> Option<Hash40> {
+    use rand::prelude::*;
 
-fn collect_stage_name_to_bgm_set(prc: &ParamStruct) -> HashMap<Hash40, Hash40> {
-    let (_, main_list) = &prc.0[0];
+    let fresh: Vec<_> = candidates
+        .iter()
+        .copied()
+        .filter(|(song, weight)| *weight > 0 && !history.contains(song))
+        .collect();
 
-    let ParamKind::List(list) = main_list else {
-        unreachable!()
+    let pool = if fresh.is_empty() {
+        candidates
+            .iter()
+            .copied()
+            .filter(|(_, weight)| *weight > 0)
+            .collect()
+    } else {
+        fresh
     };
 
-    let mut map = HashMap::with_capacity(list.0.len());
-
-    for param in list.0.iter() {
-        let ParamKind::Struct(stage) = param else {
-            continue;
-        };
+    let total: u64 = pool.iter().map(|(_, weight)| *weight as u64).sum();
+    if total == 0 {
+        return None;
+    }
 
-        let stage_place_id = prc_get(stage, hash40("stage_place_id"))
-            .and_then(|param| {
-                let ParamKind::Hash(hash) = param else {
-                    return None;
-                };
-                Some(*hash)
-            })
-            .unwrap();
-        let bgm_set_id = prc_get(stage, hash40("bgm_set_id"))
-            .and_then(|param| {
-                let ParamKind::Hash(hash) = param else {
-                    return None;
-                };
-                Some(*hash)
-            })
-            .unwrap();
+    let mut roll = rand::thread_rng().gen_range(0..total);
+    for (song, weight) in pool {
+        if roll < weight as u64 {
+            return Some(song);
+        }
 
-        map.insert(stage_place_id, bgm_set_id);
+        roll -= weight as u64;
     }
 
-    map
+    None
 }
 
-fn collect_bgm_lists(prc: &ParamStruct) -> HashMap<Hash40, Vec<Hash40>> {
-    let mut map = HashMap::new();
-    // skip 6 to start iterating over the lists
-    for (k, v) in prc.0.iter().skip(6) {
-        let ParamKind::List(list) = v else {
-            continue;
-        };
-
-        let songs: Vec<_> = list
-            .0
+impl MusicCache {
+    pub fn new(stages: &[StageDbEntry], bgm: BgmDatabase, config: &MusicConfig) -> Self {
+        let mut name_to_set: HashMap<Hash40, StagePlaylist> = stages
             .iter()
-            .filter_map(|param| {
-                let ParamKind::Struct(param) = param else {
-                    return None;
-                };
-
-                prc_get(param, hash40("ui_bgm_id")).and_then(|param| {
-                    let ParamKind::Hash(hash) = param else {
-                        return None;
-                    };
-
-                    Some(*hash)
-                })
+            .filter_map(|stage| {
+                Some((
+                    stage.stage_place_id,
+                    StagePlaylist {
+                        series: stage.bgm_set_id?,
+                        setting: stage.bgm_setting_no,
+                    },
+                ))
             })
             .collect();
 
-        map.insert(*k, songs);
-    }
-
-    map
-}
-
-impl MusicCache {
-    pub fn new(stage: &[u8], music: &[u8]) -> Self {
-        let stage = prc::read_stream(&mut std::io::Cursor::new(stage)).unwrap();
-        let bgm = prc::read_stream(&mut std::io::Cursor::new(music)).unwrap();
-
-        let name_to_set = collect_stage_name_to_bgm_set(&stage);
-        let bgm_lists = collect_bgm_lists(&bgm);
+        let mut song_overrides = HashMap::new();
+
+        for (stage, stage_override) in config.stage_overrides.iter() {
+            let stage = hash40(stage);
+
+            if !stage_override.songs.is_empty() {
+                song_overrides.insert(
+                    stage,
+                    stage_override
+                        .songs
+                        .iter()
+                        .map(|song| hash40(song))
+                        .collect(),
+                );
+            }
+
+            let current = name_to_set.get(&stage).copied();
+            let series = stage_override
+                .playlist
+                .as_ref()
+                .map(|playlist| hash40(playlist))
+                .or(current.map(|playlist| playlist.series));
+
+            if let Some(series) = series {
+                let setting = stage_override
+                    .setting
+                    .or(current.map(|playlist| playlist.setting))
+                    .unwrap_or_default()
+                    .min(PLAYLIST_COLUMNS - 1);
+
+                name_to_set.insert(stage, StagePlaylist { series, setting });
+            }
+        }
 
-        let all_songs: HashSet<Hash40> = bgm_lists
+        // Songs that music mods register are allowed even if they aren't in a playlist
+        let all_songs: HashSet<Hash40> = bgm
+            .playlists
             .values()
-            .flat_map(|bgm_set_list| bgm_set_list.iter().copied())
+            .flat_map(|bgm_set_list| bgm_set_list.iter().map(|entry| entry.song))
+            .chain(bgm.registered)
+            .chain(song_overrides.values().flatten().copied())
+            .chain(config.allowed_songs.iter().map(|song| hash40(song)))
             .collect();
 
         Self {
             allowed_songs: all_songs,
-            song_by_series: bgm_lists,
+            song_by_series: bgm.playlists,
             stage_to_series: name_to_set,
+            song_overrides,
+            policy: config.policy,
+            fallback_song: hash40(&config.fallback_song),
+            history: VecDeque::with_capacity(config.history_length),
+            history_length: config.history_length,
         }
     }
 
@@ -109,19 +137,48 @@ impl MusicCache {
         self.allowed_songs.contains(&hash)
     }
 
-    pub fn get_random_song(&self, stage_name: Hash40) -> Hash40 {
-        use rand::prelude::*;
+    /// Returns the song that should be played instead of `song` according to the music policy
+    pub fn substitute_song(&mut self, stage_name: Hash40, song: Hash40) -> Option<Hash40> {
+        match self.policy {
+            MusicPolicy::Off => None,
+            MusicPolicy::ReplaceUnknown if self.is_song_allowed(song) => None,
+            MusicPolicy::ReplaceUnknown | MusicPolicy::AlwaysRandomize => {
+                Some(self.get_random_song(stage_name))
+            }
+        }
+    }
 
-        let Some(series) = self.stage_to_series.get(&stage_name) else {
-            return hash40("ui_bgm_crs2_02_senjyou");
-        };
+    fn remember(&mut self, song: Hash40) {
+        if self.history_length == 0 {
+            return;
+        }
 
-        let Some(list) = self.song_by_series.get(&series) else {
-            return hash40("ui_bgm_crs2_02_senjyou");
+        self.history.push_back(song);
+        while self.history.len() > self.history_length {
+            self.history.pop_front();
+        }
+    }
+
+    /// Picks a song for the stage, weighted by the incidence values of the stage's playlist
+    pub fn get_random_song(&mut self, stage_name: Hash40) -> Hash40 {
+        let candidates: Vec<_> = if let Some(songs) = self.song_overrides.get(&stage_name) {
+            songs.iter().map(|song| (*song, 1)).collect()
+        } else {
+            let Some(playlist) = self.stage_to_series.get(&stage_name) else {
+                return self.fallback_song;
+            };
+
+            let Some(list) = self.song_by_series.get(&playlist.series) else {
+                return self.fallback_song;
+            };
+
+            list.iter()
+                .map(|entry| (entry.song, entry.incidence[playlist.setting]))
+                .collect()
         };
 
-        list.choose(&mut rand::thread_rng())
-            .copied()
-            .unwrap_or_else(|| hash40("ui_bgm_crs2_02_senjyou"))
+        let song = pick_weighted(&candidates, &self.history).unwrap_or(self.fallback_song);
+        self.remember(song);
+        song
     }
 }
@@ -0,0 +1,111 @@
+use crate::{
+    manifest::{self, AltManifest},
+    msbt::Msbt,
+    resources::types::ResServiceNX,
+};
+
+/// The message file with the stage names shown on the stage select and VS screens
+const MESSAGE_PATH: &str = "ui/message/msg_name.msbt";
+
+/// Room for the original file plus every name we add to it
+const MESSAGE_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+/// Region codes in the order of `ResServiceNX::language_idx`
+const LANGUAGES: [&str; 14] = [
+    "jp_ja", "us_en", "us_fr", "us_es", "eu_en", "eu_fr", "eu_es", "eu_de", "eu_nl", "eu_it",
+    "eu_ru", "kr_ko", "zh_cn", "zh_tw",
+];
+
+/// The region code of the language the game is running in
+pub fn current_language() -> &'static str {
+    ResServiceNX::instance()
+        .and_then(|service| LANGUAGES.get(service.language_idx as usize))
+        .copied()
+        .unwrap_or("us_en")
+}
+
+/// The message label an alt's name is stored under
+pub fn label_for(manifest: &AltManifest) -> String {
+    format!(
+        "nam_stage_alt_{}_{}_s{:02}",
+        manifest.stage, manifest.form, manifest.slot
+    )
+}
+
+/// The message label of a stage's own name, from its `name_id` in ui_stage_db
+pub fn stage_label(name_id: &str) -> String {
+    format!("nam_stage_name_{name_id}")
+}
+
+extern "C" fn load_stage_names(
+    hash: u64,
+    buffer: *mut u8,
+    buf_len: usize,
+    out_size: &mut usize,
+) -> bool {
+    let mut buffer = unsafe { std::slice::from_raw_parts_mut(buffer, buf_len) };
+
+    let Some(size) = arcropolis_api::load_original_file(hash, &mut buffer) else {
+        log::error!("Failed to load {MESSAGE_PATH}");
+        return false;
+    };
+
+    *out_size = size;
+
+    let mut msbt = match Msbt::parse(&buffer[..size]) {
+        Ok(msbt) => msbt,
+        Err(e) => {
+            // The original file is already in the buffer, so the game still gets its names
+            log::error!("Failed to parse {MESSAGE_PATH}: {e}");
+            return true;
+        }
+    };
+
+    let language = current_language();
+    let manifests: &'static [AltManifest] = *manifest::MANIFESTS.read();
+
+    let mut count = 0;
+    for manifest in manifests.iter() {
+        let Some(name) = manifest.display_name(language) else {
+            continue;
+        };
+
+        match msbt.set(&label_for(manifest), name) {
+            Ok(()) => count += 1,
+            Err(e) => log::warn!(
+                "Failed to add the name of alt {} of {}: {e}",
+                manifest.slot,
+                manifest.stage
+            ),
+        }
+    }
+
+    let data = msbt.to_bytes();
+    if data.len() > buf_len {
+        log::error!(
+            "{MESSAGE_PATH} with alt names is {:#x} bytes but the buffer is only {buf_len:#x}",
+            data.len()
+        );
+        return true;
+    }
+
+    buffer[..data.len()].copy_from_slice(&data);
+    *out_size = data.len();
+
+    log::info!("Added {count} alt names to {MESSAGE_PATH} for {language}");
+
+    true
+}
+
+/// Adds the names from the alt manifests to the stage name messages whenever the game loads them
+pub fn install() {
+    let manifests: &'static [AltManifest] = *manifest::MANIFESTS.read();
+    if !manifests
+        .iter()
+        .any(|manifest| manifest.name.is_some() || !manifest.names.is_empty())
+    {
+        return;
+    }
+
+    arcropolis_api::register_callback(MESSAGE_PATH, MESSAGE_BUFFER_SIZE, load_stage_names);
+}
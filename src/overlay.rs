@@ -0,0 +1,354 @@
+//! Applies overlays to the game's Lua scripts when they are loaded, so that alt support can be
+//! added to the vanilla or another mod's stage select script instead of shipping a fork of it.
+//!
+//! Overlays live in `overlays/<script>/` (e.g. `overlays/stage_select_actor3/alts.lua`) and
+//! may be source or compiled chunks. Each one returns a table keyed by the names of local
+//! functions of the script. Every value is called with the script's function once the script
+//! has run and returns the function that replaces it, so an overlay can either wrap the
+//! original or ignore it. Local functions are found through the upvalues of the functions
+//! the script stores in its globals, which only works while the script keeps its debug names.
+//!
+//! Overlays are meant for small tweaks on top of a script that already supports alts, like
+//! `overlays/stage_select_actor3/competitive.lua`. They don't replace the forked
+//! `stage_select_actor3` scripts, which still carry the alt UI and are maintained alongside
+//! the plugin. The alt UI reads input and draws panes from inside the scripts' main loops and
+//! their locals, which an overlay can't reach by wrapping named functions, so moving it out of
+//! the forks is separate work
+
+use std::{
+    collections::BTreeMap,
+    ffi::{c_char, c_void, CString},
+    path::Path,
+};
+
+use locks::RwLock;
+use rlua_lua53_sys as lua;
+
+const OVERLAY_PATH: &str = "sd:/ultimate/stage-alts/overlays";
+
+struct Overlay {
+    /// The overlay's file name, used for the chunk name and in logs
+    name: String,
+    data: Vec<u8>,
+}
+
+/// The overlays of each script, keyed by the script's file name without its extension
+static OVERLAYS: RwLock<BTreeMap<String, Vec<Overlay>>> = RwLock::new(BTreeMap::new());
+
+/// Starts the chunk name of every overlay, so that loading one isn't mistaken for a script
+const OVERLAY_CHUNK_PREFIX: &str = "=overlay:";
+
+fn read_overlays(path: &Path) -> Vec<Overlay> {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return vec![];
+    };
+
+    let mut overlays: Vec<Overlay> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map(|ty| ty.is_file()).unwrap_or(false))
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            match std::fs::read(entry.path()) {
+                Ok(data) => Some(Overlay { name, data }),
+                Err(e) => {
+                    log::error!("Failed to read overlay {name}: {e:?}");
+                    None
+                }
+            }
+        })
+        .collect();
+
+    // Overlays of the same script are applied in file name order
+    overlays.sort_by(|a, b| a.name.cmp(&b.name));
+    overlays
+}
+
+fn load() {
+    let Ok(entries) = std::fs::read_dir(OVERLAY_PATH) else {
+        return;
+    };
+
+    let mut overlays = BTreeMap::new();
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        if !entry.file_type().map(|ty| ty.is_dir()).unwrap_or(false) {
+            continue;
+        }
+
+        let Ok(script) = entry.file_name().into_string() else {
+            continue;
+        };
+
+        let script_overlays = read_overlays(&entry.path());
+        if !script_overlays.is_empty() {
+            log::info!("Loaded {} overlays for {script}", script_overlays.len());
+            overlays.insert(script, script_overlays);
+        }
+    }
+
+    *OVERLAYS.write() = overlays;
+}
+
+/// The file name of a chunk without its directory and extension, chunk names look like
+/// `@ui/script/stage_select_actor3.lc`
+fn script_name(chunk_name: &str) -> &str {
+    let name = chunk_name.trim_start_matches(['@', '=']);
+    let name = name.rsplit('/').next().unwrap_or(name);
+    name.split('.').next().unwrap_or(name)
+}
+
+unsafe fn error_message(state: *mut lua::lua_State) -> String {
+    if lua::lua_isstring(state, -1) != 0 {
+        skyline::from_c_str(lua::lua_tostring(state, -1) as _)
+    } else {
+        String::from("(no message)")
+    }
+}
+
+/// Sets the `_ENV` of the chunk on top of the stack to the value at `env`
+unsafe fn set_chunk_env(state: *mut lua::lua_State, env: i32) {
+    lua::lua_pushvalue(state, env);
+    if lua::lua_setupvalue(state, -2, 1).is_null() {
+        lua::lua_pop(state, 1);
+    }
+}
+
+/// Searches the upvalues of the Lua function at `function`, and of the functions they hold,
+/// for a function named `target`. When found, the closure that holds it is left on the stack
+/// and the index of the upvalue is returned
+unsafe fn find_upvalue(
+    state: *mut lua::lua_State,
+    function: i32,
+    target: &str,
+    visited: &mut Vec<*const c_void>,
+) -> Option<i32> {
+    let pointer = lua::lua_topointer(state, function);
+    if visited.contains(&pointer) {
+        return None;
+    }
+    visited.push(pointer);
+
+    for index in 1.. {
+        let name = lua::lua_getupvalue(state, function, index);
+        if name.is_null() {
+            break;
+        }
+
+        if lua::lua_type(state, -1) != lua::LUA_TFUNCTION || lua::lua_iscfunction(state, -1) != 0 {
+            lua::lua_pop(state, 1);
+            continue;
+        }
+
+        if skyline::from_c_str(name as _) == target {
+            lua::lua_pop(state, 1);
+            lua::lua_pushvalue(state, function);
+            return Some(index);
+        }
+
+        let inner = lua::lua_gettop(state);
+        if let Some(found) = find_upvalue(state, inner, target, visited) {
+            // Drop the inner function that sits below the closure that was found
+            lua::lua_rotate(state, -2, -1);
+            lua::lua_pop(state, 1);
+            return Some(found);
+        }
+
+        lua::lua_pop(state, 1);
+    }
+
+    None
+}
+
+/// Searches every function in the table at `env` for the local function `target`
+unsafe fn find_local_function(state: *mut lua::lua_State, env: i32, target: &str) -> Option<i32> {
+    let mut visited = vec![];
+
+    lua::lua_pushnil(state);
+    while lua::lua_next(state, env) != 0 {
+        if lua::lua_type(state, -1) == lua::LUA_TFUNCTION && lua::lua_iscfunction(state, -1) == 0 {
+            let function = lua::lua_gettop(state);
+            if let Some(found) = find_upvalue(state, function, target, &mut visited) {
+                // Leave only the closure that holds the target
+                lua::lua_rotate(state, -3, 1);
+                lua::lua_pop(state, 2);
+                return Some(found);
+            }
+        }
+
+        lua::lua_pop(state, 1);
+    }
+
+    None
+}
+
+/// Replaces a local function with what the overlay's factory on top of the stack returns for it
+unsafe fn replace_local_function(
+    state: *mut lua::lua_State,
+    env: i32,
+    script: &str,
+    overlay: &str,
+    target: &str,
+) {
+    let Some(upvalue) = find_local_function(state, env, target) else {
+        log::warn!("Overlay {overlay} targets {target}, which {script} no longer has");
+        return;
+    };
+
+    // Stack: factory, closure
+    lua::lua_pushvalue(state, -2);
+    lua::lua_getupvalue(state, -2, upvalue);
+    if lua::lua_pcall(state, 1, 1, 0) != lua::LUA_OK {
+        log::error!(
+            "Overlay {overlay} failed to replace {target}: {}",
+            error_message(state)
+        );
+        lua::lua_pop(state, 2);
+        return;
+    }
+
+    if lua::lua_type(state, -1) != lua::LUA_TFUNCTION {
+        log::warn!("Overlay {overlay} did not return a function for {target}");
+        lua::lua_pop(state, 2);
+        return;
+    }
+
+    lua::lua_setupvalue(state, -2, upvalue);
+    lua::lua_pop(state, 1);
+
+    log::info!("Overlay {overlay} replaced {target} in {script}");
+}
+
+/// Runs every overlay of a script against the script's environment at `env`
+unsafe fn apply_overlays(state: *mut lua::lua_State, env: i32, script: &str) {
+    if lua::lua_type(state, env) != lua::LUA_TTABLE {
+        log::warn!("Can't apply overlays to {script}, it has no environment");
+        return;
+    }
+
+    let overlays = OVERLAYS.read();
+    let Some(overlays) = overlays.get(script) else {
+        return;
+    };
+
+    for overlay in overlays.iter() {
+        let Ok(chunk_name) =
+            CString::new(format!("{OVERLAY_CHUNK_PREFIX}{script}/{}", overlay.name))
+        else {
+            continue;
+        };
+
+        let status = lua::luaL_loadbufferx(
+            state,
+            overlay.data.as_ptr() as _,
+            overlay.data.len(),
+            chunk_name.as_ptr(),
+            std::ptr::null(),
+        );
+
+        if status != lua::LUA_OK {
+            log::error!(
+                "Failed to load overlay {}: {}",
+                overlay.name,
+                error_message(state)
+            );
+            lua::lua_pop(state, 1);
+            continue;
+        }
+
+        // Overlays see the same globals as the script, including Alts
+        set_chunk_env(state, env);
+        if lua::lua_pcall(state, 0, 1, 0) != lua::LUA_OK {
+            log::error!(
+                "Failed to run overlay {}: {}",
+                overlay.name,
+                error_message(state)
+            );
+            lua::lua_pop(state, 1);
+            continue;
+        }
+
+        if lua::lua_type(state, -1) != lua::LUA_TTABLE {
+            log::warn!("Overlay {} did not return a table", overlay.name);
+            lua::lua_pop(state, 1);
+            continue;
+        }
+
+        let table = lua::lua_gettop(state);
+        lua::lua_pushnil(state);
+        while lua::lua_next(state, table) != 0 {
+            if lua::lua_type(state, -2) == lua::LUA_TSTRING {
+                let target = skyline::from_c_str(lua::lua_tostring(state, -2) as _);
+                replace_local_function(state, env, script, &overlay.name, &target);
+            }
+
+            lua::lua_pop(state, 1);
+        }
+
+        lua::lua_pop(state, 1);
+    }
+}
+
+/// Stands in for a script's chunk. The upvalues are the chunk's `_ENV`, which the game may
+/// replace after loading, the chunk itself and the script's name
+extern "C" fn run_script(state: *mut lua::lua_State) -> i32 {
+    unsafe {
+        let args = lua::lua_gettop(state);
+
+        lua::lua_pushvalue(state, lua::lua_upvalueindex(2));
+        set_chunk_env(state, lua::lua_upvalueindex(1));
+        lua::lua_rotate(state, 1, 1);
+
+        // Errors of the script itself are left to the game
+        lua::lua_call(state, args, lua::LUA_MULTRET);
+
+        let script = skyline::from_c_str(lua::lua_tostring(state, lua::lua_upvalueindex(3)) as _);
+        apply_overlays(state, lua::lua_upvalueindex(1), &script);
+
+        lua::lua_gettop(state)
+    }
+}
+
+#[skyline::hook(replace = lua::lua_load)]
+unsafe fn lua_load_hook(
+    state: *mut lua::lua_State,
+    reader: lua::lua_Reader,
+    data: *mut c_void,
+    chunk_name: *const c_char,
+    mode: *const c_char,
+) -> i32 {
+    let status = call_original!(state, reader, data, chunk_name, mode);
+    if status != lua::LUA_OK || chunk_name.is_null() {
+        return status;
+    }
+
+    let chunk_name = skyline::from_c_str(chunk_name as _);
+    if chunk_name.starts_with(OVERLAY_CHUNK_PREFIX) {
+        return status;
+    }
+
+    let script = script_name(&chunk_name);
+    crate::scene::script_loaded(script);
+
+    if !OVERLAYS.read().contains_key(script) {
+        return status;
+    }
+
+    log::info!("Applying overlays to {script}");
+
+    // Stack: chunk. Wrap it in run_script with its current _ENV, itself and the script name
+    if lua::lua_getupvalue(state, -1, 1).is_null() {
+        lua::lua_pushnil(state);
+    }
+    lua::lua_rotate(state, -2, 1);
+    lua::lua_pushlstring(state, script.as_ptr() as _, script.len());
+    lua::lua_pushcclosure(state, Some(run_script), 3);
+
+    status
+}
+
+/// Loads the overlays and hooks script loads, which is also how the scene tracker sees the
+/// menus it has no other hook for
+pub fn install() {
+    load();
+
+    skyline::install_hooks!(lua_load_hook);
+}
@@ -1,5 +1,6 @@
 use crate::{
-    manager::MANAGER,
+    folders,
+    manager::{StageInfo, MANAGER},
     search::SearchEx,
     utils::{ConcatHash, PrettyPath},
 };
@@ -12,12 +13,23 @@ fn file_paths_mut(arc: &mut LoadedArc) -> &mut [FilePath] {
 }
 
 /// Attempts to patch a file path by replacing the "normal" or "battle"
-/// with the same form but for the alt
+/// with the alt's folder for the same form
 fn patch_file_path(hash: Hash40, alt: usize) -> Option<PrettyPath> {
     let mut pretty = hash.pretty();
-    (pretty.replace("normal", format!("normal_s{alt:02}").as_str())
-        || pretty.replace("battle", format!("battle_s{alt:02}").as_str()))
-    .then(|| pretty)
+    let stage = *pretty.components().get(1)?;
+
+    let folder = |normal_form| {
+        folders::folder_name(
+            StageInfo {
+                name: stage,
+                normal_form,
+            },
+            alt,
+        )
+    };
+
+    (pretty.replace("normal", folder(true)) || pretty.replace("battle", folder(false)))
+        .then(|| pretty)
 }
 
 /// Patches the children of a dir info to use alt paths
@@ -0,0 +1,624 @@
+use std::collections::BTreeMap;
+
+use locks::RwLock;
+use smash_arc::Hash40;
+
+use crate::{
+    config::{self, UiPathTemplates},
+    conflicts, folders,
+    manager::{self, StageInfo, StageKind},
+    manifest::{self, AltManifest},
+    names,
+};
+
+const PLACEHOLDER_WIDTH: u32 = 256;
+const PLACEHOLDER_HEIGHT: u32 = 128;
+
+/// 3x5 bitmaps for the digits 0-9, one row per entry with the leftmost pixel in bit 2
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// 3x5 bitmaps for the letters A-Z, laid out like [`DIGITS`]
+const LETTERS: [[u8; 5]; 26] = [
+    [0b010, 0b101, 0b111, 0b101, 0b101],
+    [0b110, 0b101, 0b110, 0b101, 0b110],
+    [0b011, 0b100, 0b100, 0b100, 0b011],
+    [0b110, 0b101, 0b101, 0b101, 0b110],
+    [0b111, 0b100, 0b110, 0b100, 0b111],
+    [0b111, 0b100, 0b110, 0b100, 0b100],
+    [0b011, 0b100, 0b101, 0b101, 0b011],
+    [0b101, 0b101, 0b111, 0b101, 0b101],
+    [0b111, 0b010, 0b010, 0b010, 0b111],
+    [0b001, 0b001, 0b001, 0b101, 0b010],
+    [0b101, 0b101, 0b110, 0b101, 0b101],
+    [0b100, 0b100, 0b100, 0b100, 0b111],
+    [0b101, 0b111, 0b111, 0b101, 0b101],
+    [0b110, 0b101, 0b101, 0b101, 0b101],
+    [0b010, 0b101, 0b101, 0b101, 0b010],
+    [0b110, 0b101, 0b110, 0b100, 0b100],
+    [0b010, 0b101, 0b101, 0b110, 0b011],
+    [0b110, 0b101, 0b110, 0b101, 0b101],
+    [0b011, 0b100, 0b010, 0b001, 0b110],
+    [0b111, 0b010, 0b010, 0b010, 0b010],
+    [0b101, 0b101, 0b101, 0b101, 0b111],
+    [0b101, 0b101, 0b101, 0b101, 0b010],
+    [0b101, 0b101, 0b111, 0b111, 0b101],
+    [0b101, 0b101, 0b010, 0b101, 0b101],
+    [0b101, 0b101, 0b010, 0b010, 0b010],
+    [0b111, 0b001, 0b010, 0b100, 0b111],
+];
+
+/// The bitmap of a character, letters are drawn in upper case and anything without a glyph
+/// as a question mark
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        digit @ '0'..='9' => DIGITS[digit as usize - '0' as usize],
+        letter @ 'A'..='Z' => LETTERS[letter as usize - 'A' as usize],
+        ' ' => [0; 5],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '&' => [0b010, 0b101, 0b010, 0b101, 0b011],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+/// How many cells a line of text is wide, each glyph is 3 cells wide with a 1 cell gap
+fn text_columns(text: &str) -> usize {
+    (text.chars().count() * 4).saturating_sub(1)
+}
+
+/// Draws a line of text in white with its top left corner at the given pixel
+fn draw_text(pixels: &mut [u8], width: usize, text: &str, x: usize, y: usize, scale: usize) {
+    for (position, c) in text.chars().enumerate() {
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+
+                let cell_x = x + (position * 4 + column) * scale;
+                let cell_y = y + row * scale;
+                for y in cell_y..cell_y + scale {
+                    for x in cell_x..cell_x + scale {
+                        let offset = (y * width + x) * 4;
+                        pixels[offset..offset + 4].copy_from_slice(&[0xFF; 4]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Draws the alt number, and the alt's name below it if it has one, in white on a dark
+/// background as RGBA8 pixels. Names that don't fit are cut off
+pub fn render_placeholder(alt: usize, name: Option<&str>, width: u32, height: u32) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let mut pixels = [0x20, 0x20, 0x28, 0xFF].repeat(width * height);
+
+    let number = format!("{alt:02}");
+
+    // Names without a single letter or digit would only be drawn as question marks
+    let name = name
+        .map(str::trim)
+        .filter(|name| name.chars().any(|c| c.is_ascii_alphanumeric()))
+        .map(|name| {
+            let max_chars = (width.saturating_sub(2) + 1) / 4;
+            name.chars().take(max_chars).collect::<String>()
+        });
+
+    let Some(name) = name else {
+        let columns = text_columns(&number);
+        let scale = (width / (columns + 2)).min(height / 7).max(1);
+        let x = (width - columns * scale) / 2;
+        let y = (height - 5 * scale) / 2;
+        draw_text(&mut pixels, width, &number, x, y, scale);
+        return pixels;
+    };
+
+    // The number takes the upper half, the name goes below it with a gap of two of its cells
+    let number_columns = text_columns(&number);
+    let number_scale = (width / (number_columns + 2)).min(height / 14).max(1);
+
+    let name_columns = text_columns(&name);
+    let name_scale = (width / (name_columns + 2)).min(height / 28).max(1);
+
+    let total_height = 5 * number_scale + 7 * name_scale;
+    let top = height.saturating_sub(total_height) / 2;
+
+    draw_text(
+        &mut pixels,
+        width,
+        &number,
+        (width - number_columns * number_scale) / 2,
+        top,
+        number_scale,
+    );
+    draw_text(
+        &mut pixels,
+        width,
+        &name,
+        (width - name_columns * name_scale) / 2,
+        top + 5 * number_scale + 2 * name_scale,
+        name_scale,
+    );
+
+    pixels
+}
+
+fn align(writer: &mut Vec<u8>, alignment: usize) {
+    let aligned = (writer.len() + alignment - 1) / alignment * alignment;
+    writer.resize(aligned, 0);
+}
+
+fn write_u16(writer: &mut [u8], offset: usize, value: u16) {
+    writer[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(writer: &mut [u8], offset: usize, value: u32) {
+    writer[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u64(writer: &mut [u8], offset: usize, value: u64) {
+    writer[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn push_block_header(writer: &mut Vec<u8>, magic: &[u8; 4]) -> usize {
+    let start = writer.len();
+    writer.extend_from_slice(magic);
+    writer.resize(start + 0x10, 0);
+    start
+}
+
+/// A run of pointers that the game relocates when it loads the file
+struct Relocation {
+    offset: u32,
+    struct_count: u16,
+    offset_count: u8,
+    padding_count: u8,
+}
+
+/// Encodes a single linear R8G8B8A8 texture as a BNTX file. Linear textures don't need to be
+/// swizzled, which keeps the encoder independent from the console
+pub fn encode_bntx(name: &str, width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    const NX_HEADER: usize = 0x20;
+    const BRTI_SIZE: usize = 0xA0;
+    const MEMORY_POOL_SIZE: usize = 0x150;
+    const DATA_ALIGNMENT: usize = 0x200;
+
+    let mut data = vec![0u8; NX_HEADER + 0x38];
+
+    // String table, an empty string followed by the texture name
+    let str_start = push_block_header(&mut data, b"_STR");
+    data.extend_from_slice(&1u32.to_le_bytes());
+    data.extend_from_slice(&[0; 4]);
+    let name_offset = data.len();
+    data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    data.extend_from_slice(name.as_bytes());
+    data.push(0);
+    align(&mut data, 8);
+    let str_end = data.len();
+
+    // Dictionary with the root node and a single entry. With one key the entry branches on
+    // the lowest set bit of the name, counting from the end of the string
+    let dict_start = data.len();
+    let reference_bit = name
+        .bytes()
+        .rev()
+        .enumerate()
+        .find_map(|(index, byte)| (byte != 0).then(|| index as u32 * 8 + byte.trailing_zeros()))
+        .unwrap_or_default();
+
+    data.extend_from_slice(b"_DIC");
+    data.extend_from_slice(&1u32.to_le_bytes());
+    data.extend_from_slice(&(-1i32).to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&0u16.to_le_bytes());
+    data.extend_from_slice(&((str_start + 0x14) as u64).to_le_bytes());
+    data.extend_from_slice(&reference_bit.to_le_bytes());
+    data.extend_from_slice(&0u16.to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&(name_offset as u64).to_le_bytes());
+
+    // Scratch space the game uses at runtime
+    align(&mut data, 8);
+    let memory_pool = data.len();
+    data.resize(memory_pool + MEMORY_POOL_SIZE, 0);
+
+    // Table of pointers to each texture info
+    let info_table = data.len();
+    data.resize(info_table + 8, 0);
+
+    let brti_start = data.len();
+    data.resize(brti_start + BRTI_SIZE + 8, 0);
+
+    // The texture data starts right after the BRTD header and has to be aligned
+    let mut brtd_start = data.len();
+    if (brtd_start + 0x10) % DATA_ALIGNMENT != 0 {
+        brtd_start = ((brtd_start + 0x10) / DATA_ALIGNMENT + 1) * DATA_ALIGNMENT - 0x10;
+    }
+
+    data.resize(brtd_start, 0);
+    push_block_header(&mut data, b"BRTD");
+    let image_start = data.len();
+    data.extend_from_slice(rgba);
+    align(&mut data, 8);
+    let rlt_start = data.len();
+
+    // Texture info
+    let brti = &mut data[brti_start..];
+    brti[0..4].copy_from_slice(b"BRTI");
+    write_u32(brti, 0x04, (brtd_start - brti_start) as u32);
+    write_u32(brti, 0x08, (BRTI_SIZE + 8) as u32);
+    brti[0x10] = 1;
+    brti[0x11] = 2;
+    write_u16(brti, 0x12, 1);
+    write_u16(brti, 0x16, 1);
+    write_u16(brti, 0x18, 1);
+    write_u32(brti, 0x1C, 0x0B01);
+    write_u32(brti, 0x20, 0x20);
+    write_u32(brti, 0x24, width);
+    write_u32(brti, 0x28, height);
+    write_u32(brti, 0x2C, 1);
+    write_u32(brti, 0x30, 1);
+    write_u32(brti, 0x38, 0x10007);
+    write_u32(brti, 0x50, rgba.len() as u32);
+    write_u32(brti, 0x54, DATA_ALIGNMENT as u32);
+    write_u32(brti, 0x58, 0x05040302);
+    write_u32(brti, 0x5C, 1);
+    write_u64(brti, 0x60, name_offset as u64);
+    write_u64(brti, 0x68, NX_HEADER as u64);
+    write_u64(brti, 0x70, (brti_start + BRTI_SIZE) as u64);
+    write_u64(brti, BRTI_SIZE, image_start as u64);
+
+    write_u64(&mut data, info_table, brti_start as u64);
+
+    let brtd_size = rlt_start - brtd_start;
+    write_u32(&mut data, brtd_start + 0x08, brtd_size as u32);
+
+    // String table block links to the texture info
+    write_u32(&mut data, str_start + 0x04, (brti_start - str_start) as u32);
+    write_u32(&mut data, str_start + 0x08, (str_end - str_start) as u32);
+
+    // NX header
+    data[NX_HEADER..NX_HEADER + 4].copy_from_slice(b"NX  ");
+    write_u32(&mut data, NX_HEADER + 0x04, 1);
+    write_u64(&mut data, NX_HEADER + 0x08, info_table as u64);
+    write_u64(&mut data, NX_HEADER + 0x10, brtd_start as u64);
+    write_u64(&mut data, NX_HEADER + 0x18, dict_start as u64);
+    write_u64(&mut data, NX_HEADER + 0x20, memory_pool as u64);
+
+    // Pointers into the file body, and pointers into the texture data
+    let body_relocations = [
+        Relocation {
+            offset: (NX_HEADER + 0x08) as u32,
+            struct_count: 1,
+            offset_count: 1,
+            padding_count: 0,
+        },
+        Relocation {
+            offset: (NX_HEADER + 0x18) as u32,
+            struct_count: 1,
+            offset_count: 2,
+            padding_count: 0,
+        },
+        Relocation {
+            offset: (dict_start + 0x10) as u32,
+            struct_count: 2,
+            offset_count: 1,
+            padding_count: 1,
+        },
+        Relocation {
+            offset: info_table as u32,
+            struct_count: 1,
+            offset_count: 1,
+            padding_count: 0,
+        },
+        Relocation {
+            offset: (brti_start + 0x60) as u32,
+            struct_count: 1,
+            offset_count: 3,
+            padding_count: 0,
+        },
+    ];
+
+    let data_relocations = [
+        Relocation {
+            offset: (NX_HEADER + 0x10) as u32,
+            struct_count: 1,
+            offset_count: 1,
+            padding_count: 0,
+        },
+        Relocation {
+            offset: (brti_start + BRTI_SIZE) as u32,
+            struct_count: 1,
+            offset_count: 1,
+            padding_count: 0,
+        },
+    ];
+
+    data.extend_from_slice(b"_RLT");
+    data.extend_from_slice(&(rlt_start as u32).to_le_bytes());
+    data.extend_from_slice(&2u32.to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+
+    let sections = [
+        (0, brtd_start, 0, body_relocations.len()),
+        (
+            brtd_start,
+            rlt_start - brtd_start,
+            body_relocations.len(),
+            data_relocations.len(),
+        ),
+    ];
+
+    for (offset, size, index, count) in sections {
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&(offset as u32).to_le_bytes());
+        data.extend_from_slice(&(size as u32).to_le_bytes());
+        data.extend_from_slice(&(index as u32).to_le_bytes());
+        data.extend_from_slice(&(count as u32).to_le_bytes());
+    }
+
+    for relocation in body_relocations.iter().chain(data_relocations.iter()) {
+        data.extend_from_slice(&relocation.offset.to_le_bytes());
+        data.extend_from_slice(&relocation.struct_count.to_le_bytes());
+        data.push(relocation.offset_count);
+        data.push(relocation.padding_count);
+    }
+
+    // File header
+    let file_size = data.len();
+    data[0..8].copy_from_slice(b"BNTX\0\0\0\0");
+    write_u32(&mut data, 0x08, 0x0004_0000);
+    write_u16(&mut data, 0x0C, 0xFEFF);
+    data[0x0E] = 0x0C;
+    data[0x0F] = 0x40;
+    write_u32(&mut data, 0x10, (name_offset + 2) as u32);
+    write_u16(&mut data, 0x16, str_start as u16);
+    write_u32(&mut data, 0x18, rlt_start as u32);
+    write_u32(&mut data, 0x1C, file_size as u32);
+
+    data
+}
+
+/// Builds the placeholder texture file for an alt
+pub fn build_placeholder(alt: usize, name: Option<&str>) -> Vec<u8> {
+    let pixels = render_placeholder(alt, name, PLACEHOLDER_WIDTH, PLACEHOLDER_HEIGHT);
+    encode_bntx(
+        &format!("placeholder_s{alt:02}"),
+        PLACEHOLDER_WIDTH,
+        PLACEHOLDER_HEIGHT,
+        &pixels,
+    )
+}
+
+/// The alt a placeholder is drawn for
+struct Placeholder {
+    slot: usize,
+    manifest: Option<&'static AltManifest>,
+}
+
+/// The placeholders arcropolis asks for, keyed by the hash of their path
+static PLACEHOLDERS: RwLock<BTreeMap<u64, Placeholder>> = RwLock::new(BTreeMap::new());
+
+/// Room for a placeholder texture, the name of the texture is the only part that changes size
+const PLACEHOLDER_BUFFER_SIZE: usize = 0x40000;
+
+extern "C" fn load_placeholder(
+    hash: u64,
+    buffer: *mut u8,
+    buf_len: usize,
+    out_size: &mut usize,
+) -> bool {
+    let data = {
+        let placeholders = PLACEHOLDERS.read();
+        let Some(placeholder) = placeholders.get(&hash) else {
+            return false;
+        };
+
+        let name = placeholder
+            .manifest
+            .and_then(|manifest| manifest.display_name(names::current_language()));
+        build_placeholder(placeholder.slot, name)
+    };
+
+    if data.len() > buf_len {
+        log::error!(
+            "Placeholder {hash:#x} is {:#x} bytes but the buffer is only {buf_len:#x}",
+            data.len()
+        );
+        return false;
+    }
+
+    let buffer = unsafe { std::slice::from_raw_parts_mut(buffer, buf_len) };
+    buffer[..data.len()].copy_from_slice(&data);
+    *out_size = data.len();
+
+    true
+}
+
+/// Registers a placeholder texture for every alt UI path that no enabled mod ships. The
+/// textures are built when the game loads them and served through arcropolis, so nothing is
+/// written to the SD card. This runs at boot, after the alt folders are remapped
+pub fn install() {
+    let ui = config::CONFIG.read().ui.clone();
+    if !ui.placeholder_textures {
+        return;
+    }
+
+    let mods = conflicts::enabled_mods();
+
+    let mut placeholders = BTreeMap::new();
+    let mut paths = vec![];
+
+    for (_, mod_path) in mods.iter() {
+        let stages = mod_path.join("stage");
+
+        for stage in conflicts::read_dir_names(&stages) {
+            let stage_hash = Hash40::from(stage.as_str());
+            let kind = StageKind::from(stage_hash);
+
+            for folder in conflicts::read_dir_names(&stages.join(&stage)) {
+                let folder_hash = Hash40::from(folder.as_str());
+                let Some((slot, normal_form)) = folders::lookup(stage_hash, folder_hash) else {
+                    continue;
+                };
+
+                let info = StageInfo {
+                    name: stage_hash,
+                    normal_form,
+                };
+
+                // The normal form only shows up in the normal preview, the battle form in the
+                // battlefield and omega previews
+                let variants: &[fn(&UiPathTemplates) -> &String] = if normal_form {
+                    &[|templates| &templates.normal]
+                } else {
+                    &[|templates| &templates.battle, |templates| &templates.end]
+                };
+
+                // Other mods' copies of the folder never ship UI for the slots they are moved to
+                let slots = std::iter::once(slot).chain(folders::copies(info, folder_hash));
+
+                for (slot, get) in
+                    slots.flat_map(|slot| variants.iter().map(move |get| (slot, get)))
+                {
+                    let template = manager::ui_template(&ui, kind, *get);
+                    let path = manager::expand_ui_template_str(template, &stage, slot);
+
+                    if mods
+                        .iter()
+                        .any(|(_, mod_path)| mod_path.join(&path).exists())
+                    {
+                        continue;
+                    }
+
+                    let hash = Hash40::from(path.as_str()).0;
+                    if placeholders.contains_key(&hash) {
+                        continue;
+                    }
+
+                    placeholders.insert(
+                        hash,
+                        Placeholder {
+                            slot,
+                            manifest: manifest::find_for_folder(info, folder_hash),
+                        },
+                    );
+                    paths.push(path);
+                }
+            }
+        }
+    }
+
+    log::info!("Serving {} placeholder textures", paths.len());
+
+    *PLACEHOLDERS.write() = placeholders;
+
+    for path in paths {
+        arcropolis_api::register_callback(path.as_str(), PLACEHOLDER_BUFFER_SIZE, load_placeholder);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u16(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_u64(data: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn find(data: &[u8], magic: &[u8; 4]) -> usize {
+        data.windows(4).position(|window| window == magic).unwrap()
+    }
+
+    #[test]
+    fn bntx_header() {
+        let rgba = vec![0xAB; 16 * 8 * 4];
+        let data = encode_bntx("test_tex", 16, 8, &rgba);
+
+        assert_eq!(&data[0..8], b"BNTX\0\0\0\0");
+        assert_eq!(read_u32(&data, 0x08), 0x0004_0000);
+        assert_eq!(read_u16(&data, 0x0C), 0xFEFF);
+        assert_eq!(read_u32(&data, 0x1C) as usize, data.len());
+        assert_eq!(&data[0x20..0x24], b"NX  ");
+        assert_eq!(read_u32(&data, 0x24), 1);
+
+        // The relocation table is the last block
+        let rlt = read_u32(&data, 0x18) as usize;
+        assert_eq!(&data[rlt..rlt + 4], b"_RLT");
+
+        // The file name points right past the length of the texture name in the string table
+        let name = read_u32(&data, 0x10) as usize;
+        assert_eq!(read_u16(&data, name - 2), 8);
+        assert_eq!(&data[name..name + 9], b"test_tex\0");
+    }
+
+    #[test]
+    fn bntx_texture_layout() {
+        let rgba: Vec<u8> = (0..32 * 16 * 4).map(|byte| byte as u8).collect();
+        let data = encode_bntx("layout", 32, 16, &rgba);
+
+        // The NX header points to the table of texture infos
+        let brti = find(&data, b"BRTI");
+        let info_table = read_u64(&data, 0x28) as usize;
+        assert_eq!(read_u64(&data, info_table) as usize, brti);
+        assert_eq!(read_u16(&data, brti + 0x16), 1, "mip count");
+        assert_eq!(read_u32(&data, brti + 0x24), 32);
+        assert_eq!(read_u32(&data, brti + 0x28), 16);
+        assert_eq!(read_u32(&data, brti + 0x50) as usize, rgba.len());
+
+        // The only mip starts the texture data, aligned and holding the pixels as they are
+        let image = read_u64(&data, brti + 0xA0) as usize;
+        assert_eq!(image % 0x200, 0);
+        assert_eq!(&data[image..image + rgba.len()], &rgba[..]);
+
+        let brtd = read_u64(&data, 0x30) as usize;
+        assert_eq!(&data[brtd..brtd + 4], b"BRTD");
+        assert_eq!(brtd + 0x10, image);
+    }
+
+    #[test]
+    fn placeholder_draws_the_name() {
+        let without_name = render_placeholder(5, None, 64, 32);
+        let with_name = render_placeholder(5, Some("Ruins"), 64, 32);
+        let unprintable = render_placeholder(5, Some("\u{907a}\u{8de1}"), 64, 32);
+
+        assert_eq!(with_name.len(), 64 * 32 * 4);
+        assert_ne!(without_name, with_name);
+        assert_eq!(without_name, unprintable);
+    }
+
+    #[test]
+    fn placeholder_cuts_off_long_names() {
+        let name = "A".repeat(100);
+        let pixels = render_placeholder(1, Some(&name), 32, 32);
+        assert_eq!(pixels.len(), 32 * 32 * 4);
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+
+use smash_arc::Hash40;
+
+use crate::{
+    manager::{AltField, AltManager, StageInfo},
+    storage::{PendingWrite, Stored, TextTable},
+};
+
+const REPLAY_INDEX_PATH: &str = "sd:/ultimate/stage-alts/replay_alts.txt";
+
+/// Identifies a match the same way the replay file does. The stage id and the BGM id (which
+/// also holds our alt field) are saved with the replay and handed back to us when it is played
+/// back, so they are the only identity we can rely on
+#[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug)]
+pub struct ReplayKey {
+    pub stage_id: u32,
+    pub bgm_id: u64,
+}
+
+/// The alt that was played in a match, 0 being the base stage
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct ReplayEntry {
+    pub info: StageInfo,
+    pub slot: usize,
+}
+
+pub struct ReplayTable(BTreeMap<ReplayKey, ReplayEntry>);
+
+impl TextTable for ReplayTable {
+    /// Parses the replay index, each line is `<stage id> <bgm id> <stage hash> <form> <slot>`
+    /// where the form is `normal` or `battle`
+    fn parse(data: &str) -> Self {
+        let mut entries = BTreeMap::new();
+
+        for line in data.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let [stage_id, bgm_id, stage, form, slot] = fields[..] else {
+                log::warn!("Skipping malformed replay index line '{line}'");
+                continue;
+            };
+
+            let normal_form = match form {
+                "normal" => true,
+                "battle" => false,
+                _ => {
+                    log::warn!("Skipping malformed replay index line '{line}'");
+                    continue;
+                }
+            };
+
+            let parse_hex = |value: &str| u64::from_str_radix(value.trim_start_matches("0x"), 16);
+
+            let (Ok(stage_id), Ok(bgm_id), Ok(stage), Ok(slot)) = (
+                parse_hex(stage_id),
+                parse_hex(bgm_id),
+                parse_hex(stage),
+                slot.parse::<usize>(),
+            ) else {
+                log::warn!("Skipping malformed replay index line '{line}'");
+                continue;
+            };
+
+            entries.insert(
+                ReplayKey {
+                    stage_id: stage_id as u32,
+                    bgm_id,
+                },
+                ReplayEntry {
+                    info: StageInfo {
+                        name: Hash40(stage),
+                        normal_form,
+                    },
+                    slot,
+                },
+            );
+        }
+
+        Self(entries)
+    }
+
+    fn serialize(&self) -> String {
+        let mut data = String::new();
+        for (key, entry) in self.0.iter() {
+            data.push_str(&format!(
+                "{:#x} {:#018x} {:#012x} {} {}\n",
+                key.stage_id,
+                key.bgm_id,
+                entry.info.name.0,
+                if entry.info.normal_form {
+                    "normal"
+                } else {
+                    "battle"
+                },
+                entry.slot
+            ));
+        }
+
+        data
+    }
+}
+
+/// The alt that was played in every recorded match, so that its replay can show the same alt
+/// even when the alt field in the BGM id doesn't survive
+pub struct ReplayIndex {
+    entries: Stored<ReplayTable>,
+}
+
+impl ReplayIndex {
+    pub const fn new() -> Self {
+        Self {
+            entries: Stored::new(REPLAY_INDEX_PATH, ReplayTable(BTreeMap::new())),
+        }
+    }
+
+    pub fn get(&mut self, key: ReplayKey) -> Option<ReplayEntry> {
+        self.entries.get().0.get(&key).copied()
+    }
+
+    /// Remembers the alt of a match, the index is written on the next save
+    pub fn record(&mut self, key: ReplayKey, entry: ReplayEntry) {
+        if self.get(key) != Some(entry) {
+            self.entries.get_mut().0.insert(key, entry);
+        }
+    }
+
+    pub fn take_pending_write(&mut self) -> Option<PendingWrite> {
+        self.entries.take_pending_write()
+    }
+}
+
+/// Resolves the stage form and alt slot for a match that is being played back. Matches in the
+/// index restore the alt that was recorded for them. Anything else falls back to the alt field
+/// saved in the BGM id, replays saved by older builds only have the alt's position in the list,
+/// which is resolved as it is listed now
+pub fn resolve_alt(
+    mgr: &mut AltManager,
+    key: ReplayKey,
+    stage: Hash40,
+    field: AltField,
+) -> (StageInfo, Option<usize>) {
+    if let Some(entry) = mgr.replays.get(key) {
+        if entry.slot == 0 {
+            return (entry.info, None);
+        }
+
+        if mgr.find_alt_by_slot(entry.info, entry.slot).is_none() {
+            log::warn!(
+                "Replay was recorded on alt {} of {}, but that alt is no longer installed",
+                entry.slot,
+                crate::utils::string_for_hash(entry.info.name)
+            );
+            return (entry.info, None);
+        }
+
+        return (entry.info, Some(entry.slot));
+    }
+
+    let info = field.stage_info(stage);
+    let alt = match field {
+        AltField::Slot { slot, .. } if slot != 0 && mgr.find_alt_by_slot(info, slot).is_none() => {
+            log::warn!(
+                "Replay was recorded on alt {slot} of {}, but that alt is no longer installed",
+                crate::utils::string_for_hash(stage)
+            );
+            None
+        }
+        AltField::Position(index) if index != 0 => {
+            log::warn!(
+                "Replay was recorded by an older version, alt {index} of {} may not be the one that was played",
+                crate::utils::string_for_hash(stage)
+            );
+            mgr.resolve_alt_field(stage, field)
+        }
+        _ => mgr.resolve_alt_field(stage, field),
+    };
+
+    (info, alt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_round_trips() {
+        let key = ReplayKey {
+            stage_id: 0x5a,
+            bgm_id: 0x0002_1500_1234_5678,
+        };
+        let entry = ReplayEntry {
+            info: StageInfo {
+                name: Hash40(0x0b_1234_5678),
+                normal_form: false,
+            },
+            slot: 21,
+        };
+
+        let table = ReplayTable(BTreeMap::from([(key, entry)]));
+        let parsed = ReplayTable::parse(&table.serialize());
+        assert_eq!(parsed.0.get(&key), Some(&entry));
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let table = ReplayTable::parse(
+            "0x5a 0x1 0xb12345678 normal 3\nnot a line\n0x5b 0x2 0xb12345678 sideways 4\n",
+        );
+        assert_eq!(table.0.len(), 1);
+    }
+}
@@ -0,0 +1,262 @@
+use locks::Mutex;
+
+static SCENE: Mutex<SceneTracker> = Mutex::new(SceneTracker::new());
+
+/// The part of the game we believe the player is currently in
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Scene {
+    Menu,
+    LocalStageSelect,
+    LocalMatch,
+    OnlineArena,
+    OnlineQuickplay,
+    Training,
+    Replay,
+    Results,
+}
+
+impl Scene {
+    pub fn is_online(self) -> bool {
+        matches!(self, Self::OnlineArena | Self::OnlineQuickplay)
+    }
+
+    /// A local match or what follows it, the scenes the stage select is entered from after one
+    pub fn is_match(self) -> bool {
+        matches!(self, Self::LocalMatch | Self::Training | Self::Results)
+    }
+}
+
+/// Events that drive the scene state machine. They come from our own hooks, and other plugins
+/// can inject them with [`stage_alts_notify_scene`] as well
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SceneEvent {
+    MainMenu = 0,
+    OnlineQuickplay = 1,
+    BackgroundMatchmaking = 2,
+    OnlineArena = 3,
+    StageSelect = 4,
+    MatchLoad = 5,
+    TrainingLoad = 6,
+    Replay = 7,
+    Results = 8,
+}
+
+impl SceneEvent {
+    pub fn from_raw(raw: u32) -> Option<Self> {
+        let event = match raw {
+            0 => Self::MainMenu,
+            1 => Self::OnlineQuickplay,
+            2 => Self::BackgroundMatchmaking,
+            3 => Self::OnlineArena,
+            4 => Self::StageSelect,
+            5 => Self::MatchLoad,
+            6 => Self::TrainingLoad,
+            7 => Self::Replay,
+            8 => Self::Results,
+            _ => return None,
+        };
+
+        Some(event)
+    }
+}
+
+pub struct SceneTracker {
+    current: Scene,
+}
+
+impl SceneTracker {
+    pub const fn new() -> Self {
+        Self {
+            current: Scene::Menu,
+        }
+    }
+
+    pub fn current(&self) -> Scene {
+        self.current
+    }
+
+    /// Computes the scene that follows `current` once `event` has happened. Online scenes
+    /// are only left by going back to the main menu, since the stage select and match
+    /// events also fire while playing online.
+    ///
+    /// Replays are only entered through [`SceneEvent::Replay`], and matches that load while
+    /// one plays back (like the stages of a stage morph) belong to it, as does any result
+    /// stage that loads
+    pub fn transition(current: Scene, event: SceneEvent) -> Scene {
+        match event {
+            SceneEvent::MainMenu => Scene::Menu,
+            SceneEvent::OnlineQuickplay | SceneEvent::BackgroundMatchmaking => {
+                Scene::OnlineQuickplay
+            }
+            SceneEvent::OnlineArena => Scene::OnlineArena,
+            _ if current.is_online() => current,
+            SceneEvent::StageSelect => Scene::LocalStageSelect,
+            SceneEvent::MatchLoad | SceneEvent::Results if current == Scene::Replay => {
+                Scene::Replay
+            }
+            SceneEvent::MatchLoad => Scene::LocalMatch,
+            SceneEvent::TrainingLoad => Scene::Training,
+            SceneEvent::Replay => Scene::Replay,
+            SceneEvent::Results => Scene::Results,
+        }
+    }
+
+    pub fn handle(&mut self, event: SceneEvent) -> Scene {
+        let next = Self::transition(self.current, event);
+
+        if next != self.current {
+            log::info!(
+                "Scene changed from {:?} to {:?} ({:?})",
+                self.current,
+                next,
+                event
+            );
+        }
+
+        self.current = next;
+        next
+    }
+}
+
+/// The UI scripts of the vault's replay menu start with this. Replays can only be played back
+/// from there, so every match that loads after it opened is a replay until the main menu
+const REPLAY_SCRIPT_PREFIX: &str = "replay";
+
+/// Called with the file name of every UI script the game loads, for the scenes that have no
+/// hook of their own
+pub fn script_loaded(script: &str) {
+    if script.starts_with(REPLAY_SCRIPT_PREFIX) {
+        dispatch(SceneEvent::Replay);
+    }
+}
+
+/// Feeds an event into the global scene tracker, returning the new scene
+pub fn dispatch(event: SceneEvent) -> Scene {
+    SCENE.lock().handle(event)
+}
+
+pub fn current() -> Scene {
+    SCENE.lock().current()
+}
+
+/// Allows other plugins to report scene changes, for modes our hooks don't recognize
+#[no_mangle]
+pub extern "C" fn stage_alts_notify_scene(event: u32) -> bool {
+    let Some(event) = SceneEvent::from_raw(event) else {
+        log::warn!("Ignoring unknown scene event {event}");
+        return false;
+    };
+
+    dispatch(event);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(events: &[SceneEvent]) -> Scene {
+        events.iter().fold(Scene::Menu, |scene, event| {
+            SceneTracker::transition(scene, *event)
+        })
+    }
+
+    #[test]
+    fn local_match_goes_through_stage_select() {
+        assert_eq!(
+            run(&[SceneEvent::StageSelect, SceneEvent::MatchLoad]),
+            Scene::LocalMatch
+        );
+        assert_eq!(
+            run(&[
+                SceneEvent::StageSelect,
+                SceneEvent::MatchLoad,
+                SceneEvent::Results
+            ]),
+            Scene::Results
+        );
+    }
+
+    #[test]
+    fn only_local_matches_lead_back_to_a_save() {
+        let after_match = run(&[
+            SceneEvent::StageSelect,
+            SceneEvent::MatchLoad,
+            SceneEvent::Results,
+        ]);
+        assert!(after_match.is_match());
+        assert!(!run(&[SceneEvent::StageSelect]).is_match());
+        assert!(!run(&[SceneEvent::Replay, SceneEvent::MatchLoad]).is_match());
+    }
+
+    #[test]
+    fn match_from_menu_is_not_a_replay() {
+        assert_eq!(run(&[SceneEvent::MatchLoad]), Scene::LocalMatch);
+    }
+
+    #[test]
+    fn replay_lasts_until_main_menu() {
+        let events = [
+            SceneEvent::Replay,
+            SceneEvent::MatchLoad,
+            SceneEvent::MatchLoad,
+        ];
+        assert_eq!(run(&events), Scene::Replay);
+        assert_eq!(
+            SceneTracker::transition(Scene::Replay, SceneEvent::Results),
+            Scene::Replay
+        );
+        assert_eq!(
+            SceneTracker::transition(Scene::Replay, SceneEvent::MainMenu),
+            Scene::Menu
+        );
+    }
+
+    #[test]
+    fn online_ignores_local_events() {
+        for online in [SceneEvent::OnlineArena, SceneEvent::OnlineQuickplay] {
+            let scene = run(&[online]);
+            assert!(scene.is_online());
+
+            for event in [
+                SceneEvent::StageSelect,
+                SceneEvent::MatchLoad,
+                SceneEvent::TrainingLoad,
+                SceneEvent::Replay,
+                SceneEvent::Results,
+            ] {
+                assert_eq!(SceneTracker::transition(scene, event), scene);
+            }
+
+            assert_eq!(
+                SceneTracker::transition(scene, SceneEvent::MainMenu),
+                Scene::Menu
+            );
+        }
+    }
+
+    #[test]
+    fn background_matchmaking_is_quickplay() {
+        assert_eq!(
+            run(&[SceneEvent::StageSelect, SceneEvent::BackgroundMatchmaking]),
+            Scene::OnlineQuickplay
+        );
+    }
+
+    #[test]
+    fn training() {
+        assert_eq!(run(&[SceneEvent::TrainingLoad]), Scene::Training);
+    }
+
+    #[test]
+    fn raw_events_round_trip() {
+        for raw in 0..=8 {
+            assert_eq!(
+                SceneEvent::from_raw(raw).map(|event| event as u32),
+                Some(raw)
+            );
+        }
+        assert_eq!(SceneEvent::from_raw(9), None);
+    }
+}
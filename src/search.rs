@@ -1,14 +1,17 @@
 use smash_arc::{
     ArcLookup, FolderPathListEntry, Hash40, HashToIndex, LoadedArc, LoadedSearchSection,
-    LookupError, PathListEntry, SearchLookup,
+    LookupError, PathListEntry, Region, SearchLookup,
 };
 use std::{
     collections::{BTreeMap, HashMap},
     fmt::Display,
+    path::{Path, PathBuf},
 };
 
 use crate::{
-    manager::{AltInfo, StageInfo, StageKind, UiPaths},
+    conflicts, folders,
+    manager::{self, AltInfo, StageInfo, StageKind, UiPaths},
+    manifest,
     resources::types::FilesystemInfo,
     utils::ConcatHash,
 };
@@ -306,42 +309,196 @@ pub fn get_search_lookup() -> HashMap<Hash40, String> {
     )
 }
 
-fn guess_hash(hash: Hash40) -> Option<(usize, bool)> {
-    match hash.len() {
-        10 => {
-            for x in 1..100 {
-                let mut string = format!("normal_s{x:02}");
-                if Hash40::from(string.as_str()) == hash {
-                    return Some((x, true));
-                }
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
 
-                string.replace_range(..6, "battle");
-                if Hash40::from(string.as_str()) == hash {
-                    return Some((x, false));
-                }
-            }
+fn fnv(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// A file of an alt folder, keyed by its path relative to the folder so that the same alt
+/// gets the same fingerprint in any slot or folder
+pub struct RelativeFile {
+    key: u64,
+    size: usize,
+}
+
+fn collect_relative_files(
+    arc: &LoadedArc,
+    search: &LoadedSearchSection,
+    folder: Hash40,
+    parent_key: u64,
+    files: &mut Vec<RelativeFile>,
+) {
+    let Ok(folder) = search.get_folder_path_entry_from_hash(folder) else {
+        return;
+    };
+
+    let mut index = folder.get_first_child_index();
+
+    while index < 0x00FF_FFFF {
+        let path = &search.get_path_list()[index];
+        index = path.path.index() as usize;
+
+        let key = fnv(parent_key, &path.file_name.hash40().0.to_le_bytes());
+
+        if path.is_directory() {
+            collect_relative_files(arc, search, path.path.hash40(), key, files);
+            continue;
         }
-        11 => {
-            for x in 100..1000 {
-                let mut string = format!("normal_s{x:03}");
-                if Hash40::from(string.as_str()) == hash {
-                    return Some((x, true));
-                }
 
-                string.replace_range(..6, "battle");
-                if Hash40::from(string.as_str()) == hash {
-                    return Some((x, false));
-                }
+        let size = arc
+            .get_file_info_from_hash(path.path.hash40())
+            .map(|info| arc.get_file_data(info, Region::None).decomp_size as usize)
+            .unwrap_or_default();
+
+        files.push(RelativeFile { key, size });
+    }
+}
+
+/// Lists the files of a folder for [`fingerprint_files`]. This only reads the filesystem
+/// tables, so it is cheap enough to run at boot
+pub fn relative_files(
+    arc: &LoadedArc,
+    search: &LoadedSearchSection,
+    path: Hash40,
+) -> Vec<RelativeFile> {
+    let mut files = vec![];
+    collect_relative_files(arc, search, path, FNV_OFFSET, &mut files);
+
+    // The search section order depends on the order mods were discovered in
+    files.sort_unstable_by_key(|file| file.key);
+    files
+}
+
+/// Lists the files of an alt folder in a mod, keyed like [`RelativeFile`]
+fn collect_mod_files(folder: &Path, parent_key: u64, files: &mut HashMap<u64, PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(folder) else {
+        return;
+    };
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+
+        let key = fnv(parent_key, &Hash40::from(name.as_str()).0.to_le_bytes());
+
+        if entry.file_type().map(|ty| ty.is_dir()).unwrap_or(false) {
+            collect_mod_files(&entry.path(), key, files);
+        } else {
+            // A file that more than one mod ships is read from the first of them
+            files.entry(key).or_insert_with(|| entry.path());
+        }
+    }
+}
+
+/// Finds the folders of every enabled mod that provide an alt folder, keyed by the stage and
+/// the alt folder's name
+fn mod_alt_folders() -> HashMap<(Hash40, Hash40), Vec<PathBuf>> {
+    let mut folders: HashMap<_, Vec<_>> = HashMap::new();
+
+    for (_, mod_path) in conflicts::enabled_mods() {
+        let stages = mod_path.join("stage");
+
+        for stage in conflicts::read_dir_names(&stages) {
+            for folder in conflicts::read_dir_names(&stages.join(&stage)) {
+                folders
+                    .entry((Hash40::from(stage.as_str()), Hash40::from(folder.as_str())))
+                    .or_default()
+                    .push(stages.join(&stage).join(&folder));
             }
         }
-        _ => {}
     }
 
-    None
+    folders
+}
+
+/// Computes a stable fingerprint of the contents of a folder from the relative path,
+/// decompressed size and data of every file in it. Alt folders only exist in mods and the arc
+/// only knows the vanilla data of a file, so the data is read from the mods' files
+pub fn fingerprint_files(files: &[RelativeFile], mod_files: &HashMap<u64, PathBuf>) -> u64 {
+    files.iter().fold(FNV_OFFSET, |hash, file| {
+        let hash = fnv(hash, &file.key.to_le_bytes());
+        let hash = fnv(hash, &(file.size as u64).to_le_bytes());
+
+        let Some(path) = mod_files.get(&file.key) else {
+            return hash;
+        };
+
+        match std::fs::read(path) {
+            Ok(data) => fnv(hash, &data),
+            Err(e) => {
+                log::warn!(
+                    "Failed to read {} for its fingerprint: {e:?}",
+                    path.display()
+                );
+                hash
+            }
+        }
+    })
+}
+
+/// Computes the fingerprint of every alt on another thread, reading the data of every alt
+/// file would hold up the boot. Fingerprints read as 0 until they are done
+pub fn start_fingerprinting(alts: &BTreeMap<StageInfo, Vec<AltInfo>>) {
+    let fs = FilesystemInfo::instance().unwrap();
+
+    let jobs: Vec<_> = alts
+        .iter()
+        .flat_map(|(info, alts)| alts.iter().map(move |alt| (*info, alt.slot_value)))
+        .map(|(info, slot)| {
+            let folder_name = folders::folder_name(info, slot);
+            let folder = Hash40::from("stage/")
+                .concat(info.name)
+                .concat("/")
+                .concat(folder_name);
+
+            (
+                info,
+                slot,
+                folder_name,
+                relative_files(fs.arc(), fs.search(), folder),
+                conflicts::copy_folder(info, slot),
+            )
+        })
+        .collect();
+
+    std::thread::spawn(move || {
+        let mod_folders = mod_alt_folders();
+
+        let fingerprints: Vec<_> = jobs
+            .into_iter()
+            .map(|(info, slot, folder_name, files, copy)| {
+                // Another mod's copy of a folder reads its own files before the merged ones
+                let mut mod_files = HashMap::new();
+                for folder in copy.iter().chain(
+                    mod_folders
+                        .get(&(info.name, folder_name))
+                        .into_iter()
+                        .flatten(),
+                ) {
+                    collect_mod_files(folder, FNV_OFFSET, &mut mod_files);
+                }
+
+                (info, slot, fingerprint_files(&files, &mod_files))
+            })
+            .collect();
+
+        let mut mgr = manager::MANAGER.write();
+        for (info, slot, fingerprint) in fingerprints.iter() {
+            mgr.set_fingerprint(*info, *slot, *fingerprint);
+        }
+
+        log::info!("Computed the fingerprints of {} alts", fingerprints.len());
+    });
 }
 
 pub fn build_alt_lookups() -> BTreeMap<StageInfo, Vec<AltInfo>> {
-    let search = FilesystemInfo::instance().unwrap().search();
+    let fs = FilesystemInfo::instance().unwrap();
+    let search = fs.search();
 
     let Ok(folder) = search.get_folder_path_entry_from_hash("stage") else {
         log::error!("Can't find stage folder -- what the FUCK did you do?");
@@ -374,17 +531,37 @@ pub fn build_alt_lookups() -> BTreeMap<StageInfo, Vec<AltInfo>> {
             let path = search.get_path_list()[child_index];
             child_index = path.path.index() as usize;
 
-            if let Some((alt_id, is_normal)) = guess_hash(path.file_name.hash40()) {
-                map.entry(StageInfo {
-                    name: child_folder.file_name.hash40(),
+            let stage = child_folder.file_name.hash40();
+            if let Some((alt_id, is_normal)) = folders::lookup(stage, path.file_name.hash40()) {
+                let info = StageInfo {
+                    name: stage,
                     normal_form: is_normal,
-                })
-                .or_default()
-                .push(AltInfo {
-                    slot_value: alt_id,
-                    wifi_safe: true,
-                    ui_paths: UiPaths::new(StageKind::from(parent.file_name.hash40()), alt_id),
-                });
+                };
+
+                let alts = map.entry(info).or_default();
+                if alts.iter().any(|alt| alt.slot_value == alt_id) {
+                    log::warn!(
+                        "Alt {alt_id} of {} is provided by more than one folder, ignoring {}",
+                        crate::utils::string_for_hash(stage),
+                        path.path.hash40().pretty()
+                    );
+                    continue;
+                }
+
+                let manifest = manifest::find_for_folder(info, path.file_name.hash40());
+
+                // Other mods' copies of the folder load from it too, under slots of their own
+                let copies = folders::copies(info, path.file_name.hash40());
+                for slot in std::iter::once(alt_id).chain(copies) {
+                    alts.push(AltInfo {
+                        slot_value: slot,
+                        wifi_safe: true,
+                        ui_paths: UiPaths::new(StageKind::from(parent.file_name.hash40()), slot),
+                        fingerprint: 0,
+                        manifest,
+                        tags: manager::alt_tags(info, slot, manifest),
+                    });
+                }
             }
         }
     }
@@ -440,9 +617,21 @@ pub fn recreate_search_path_hash<'a>(
     let new_name = if alt == 0 {
         path.file_name.hash40()
     } else {
-        path.file_name
-            .hash40()
-            .concat(format!("_s{alt:02}").as_str())
+        let Ok(stage) = search.get_path_list_entry_from_hash(path.parent.hash40()) else {
+            log::error!(
+                "Failed to get stage path entry, stage is '{}'",
+                path.parent.hash40().pretty()
+            );
+            return None;
+        };
+
+        folders::folder_name(
+            StageInfo {
+                name: stage.file_name.hash40(),
+                normal_form: path.file_name.hash40() == Hash40::from("normal"),
+            },
+            alt,
+        )
     };
 
     let mut full_path = path.parent.hash40().concat("/").concat(new_name);
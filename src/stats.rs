@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+
+use smash_arc::Hash40;
+
+use crate::storage::{PendingWrite, Stored, TextTable};
+
+const STATS_PATH: &str = "sd:/ultimate/stage-alts/alt_stats.txt";
+const CSV_PATH: &str = "sd:/ultimate/stage-alts/alt_stats.csv";
+const JSON_PATH: &str = "sd:/ultimate/stage-alts/alt_stats.json";
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Debug)]
+pub struct StatsKey {
+    pub stage: Hash40,
+    pub normal_form: bool,
+
+    /// The alt slot, 0 is the base stage
+    pub slot: usize,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AltStats {
+    pub plays: u32,
+
+    /// Seconds since the unix epoch, 0 if the alt was never played
+    pub last_played: u64,
+}
+
+/// The stats of every alt that was played
+pub struct StatsTable(BTreeMap<StatsKey, AltStats>);
+
+pub struct PlayStats {
+    entries: Stored<StatsTable>,
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default()
+}
+
+fn escape_json(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl TextTable for StatsTable {
+    /// Parses the stats file, each line is `<stage hash> <form> <slot> <plays> <last played>`
+    /// where the form is `normal` or `battle`
+    fn parse(data: &str) -> Self {
+        let mut entries = BTreeMap::new();
+
+        for line in data.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let [stage, form, slot, plays, last_played] = fields[..] else {
+                log::warn!("Skipping malformed stats line '{line}'");
+                continue;
+            };
+
+            let normal_form = match form {
+                "normal" => true,
+                "battle" => false,
+                _ => {
+                    log::warn!("Skipping malformed stats line '{line}'");
+                    continue;
+                }
+            };
+
+            let (Ok(stage), Ok(slot), Ok(plays), Ok(last_played)) = (
+                u64::from_str_radix(stage.trim_start_matches("0x"), 16),
+                slot.parse::<usize>(),
+                plays.parse::<u32>(),
+                last_played.parse::<u64>(),
+            ) else {
+                log::warn!("Skipping malformed stats line '{line}'");
+                continue;
+            };
+
+            entries.insert(
+                StatsKey {
+                    stage: Hash40(stage),
+                    normal_form,
+                    slot,
+                },
+                AltStats { plays, last_played },
+            );
+        }
+
+        Self(entries)
+    }
+
+    fn serialize(&self) -> String {
+        let mut data = String::new();
+        for (key, stats) in self.0.iter() {
+            data.push_str(&format!(
+                "{:#012x} {} {} {} {}\n",
+                key.stage.0,
+                if key.normal_form { "normal" } else { "battle" },
+                key.slot,
+                stats.plays,
+                stats.last_played
+            ));
+        }
+
+        data
+    }
+}
+
+impl StatsTable {
+    pub fn to_csv(&self) -> String {
+        let mut data = String::from("stage,form,slot,plays,last_played\n");
+        for (key, stats) in self.0.iter() {
+            data.push_str(&format!(
+                "{},{},{},{},{}\n",
+                crate::utils::string_for_hash(key.stage),
+                if key.normal_form { "normal" } else { "battle" },
+                key.slot,
+                stats.plays,
+                stats.last_played
+            ));
+        }
+
+        data
+    }
+
+    pub fn to_json(&self) -> String {
+        let entries: Vec<_> = self
+            .0
+            .iter()
+            .map(|(key, stats)| {
+                format!(
+                    "  {{\"stage\": \"{}\", \"form\": \"{}\", \"slot\": {}, \"plays\": {}, \"last_played\": {}}}",
+                    escape_json(&crate::utils::string_for_hash(key.stage)),
+                    if key.normal_form { "normal" } else { "battle" },
+                    key.slot,
+                    stats.plays,
+                    stats.last_played
+                )
+            })
+            .collect();
+
+        format!("[\n{}\n]\n", entries.join(",\n"))
+    }
+}
+
+impl PlayStats {
+    pub const fn new() -> Self {
+        Self {
+            entries: Stored::new(STATS_PATH, StatsTable(BTreeMap::new())),
+        }
+    }
+
+    pub fn get(&mut self, key: StatsKey) -> AltStats {
+        self.entries.get().0.get(&key).copied().unwrap_or_default()
+    }
+
+    /// Counts a play of an alt, the stats file is written on the next save
+    pub fn record(&mut self, key: StatsKey) {
+        let stats = self.entries.get_mut().0.entry(key).or_default();
+        stats.plays += 1;
+        stats.last_played = now();
+    }
+
+    pub fn take_pending_write(&mut self) -> Option<PendingWrite> {
+        self.entries.take_pending_write()
+    }
+
+    /// The stats as CSV and JSON files next to the stats file, along with the stats file
+    /// itself if it has changes that weren't saved yet
+    pub fn export(&mut self) -> Vec<PendingWrite> {
+        let table = self.entries.get();
+        log::info!("Exporting stats for {} alts", table.0.len());
+
+        let mut writes = vec![
+            PendingWrite::new(CSV_PATH, table.to_csv()),
+            PendingWrite::new(JSON_PATH, table.to_json()),
+        ];
+        writes.extend(self.take_pending_write());
+        writes
+    }
+}
@@ -0,0 +1,104 @@
+//! Tables that are kept in text files on the SD card. They are read the first time they are
+//! used, and changes are only written when [`save_pending`] runs, so that hooks which record
+//! something while holding [`MANAGER`] never touch the SD card
+
+use crate::manager::MANAGER;
+
+pub const DATA_PATH: &str = "sd:/ultimate/stage-alts";
+
+/// A table that can be read from and written to a text file
+pub trait TextTable: Sized {
+    /// Parses the file, lines that can't be read are skipped
+    fn parse(data: &str) -> Self;
+    fn serialize(&self) -> String;
+}
+
+/// The contents of a file that is waiting to be written
+pub struct PendingWrite {
+    path: &'static str,
+    data: String,
+}
+
+impl PendingWrite {
+    pub fn new(path: &'static str, data: String) -> Self {
+        Self { path, data }
+    }
+
+    pub fn write(self) {
+        if let Err(e) =
+            std::fs::create_dir_all(DATA_PATH).and_then(|_| std::fs::write(self.path, self.data))
+        {
+            log::error!("Failed to write {}: {e:?}", self.path);
+        }
+    }
+}
+
+pub struct Stored<T> {
+    path: &'static str,
+    table: T,
+    loaded: bool,
+    dirty: bool,
+}
+
+impl<T: TextTable> Stored<T> {
+    pub const fn new(path: &'static str, table: T) -> Self {
+        Self {
+            path,
+            table,
+            loaded: false,
+            dirty: false,
+        }
+    }
+
+    fn ensure_loaded(&mut self) {
+        if self.loaded {
+            return;
+        }
+
+        self.loaded = true;
+
+        match std::fs::read_to_string(self.path) {
+            Ok(data) => self.table = T::parse(&data),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::error!("Failed to read {}: {e:?}", self.path),
+        }
+    }
+
+    pub fn get(&mut self) -> &T {
+        self.ensure_loaded();
+        &self.table
+    }
+
+    /// The table, which is written on the next save
+    pub fn get_mut(&mut self) -> &mut T {
+        self.ensure_loaded();
+        self.dirty = true;
+        &mut self.table
+    }
+
+    /// Serializes the table if it changed since the last save, so that the file can be
+    /// written after the lock that guards the table is released
+    pub fn take_pending_write(&mut self) -> Option<PendingWrite> {
+        if !std::mem::take(&mut self.dirty) {
+            return None;
+        }
+
+        Some(PendingWrite::new(self.path, self.table.serialize()))
+    }
+}
+
+/// Writes every table that changed since the last save
+pub fn save_pending() {
+    let writes = {
+        let mut mgr = MANAGER.write();
+        [
+            mgr.stats.take_pending_write(),
+            mgr.favorites.take_pending_write(),
+            mgr.replays.take_pending_write(),
+        ]
+    };
+
+    for write in writes.into_iter().flatten() {
+        write.write();
+    }
+}
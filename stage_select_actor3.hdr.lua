@@ -81,6 +81,14 @@ local SCENE_STATE_EXITED      = 3 -- R25
 local INPUT_STRIKE = VI_BUTTON_EXTRA29
 local INPUT_ALT_R = VI_BUTTON_EXTRA28
 local INPUT_ALT_L = VI_BUTTON_EXTRA27
+local INPUT_ALT_FAVORITE = VI_BUTTON_EXTRA26
+
+-- The Alts.api_version this script is written against. Plugins older than that only count alts
+-- and look up their textures, so the script cycles through alts by itself on them
+local ALTS_API_VERSION = 6
+
+-- Whether the plugin supports ALTS_API_VERSION, checked once in setup
+local is_alts_api_current = false
 
 exit_code_ = nil
 
@@ -311,6 +319,52 @@ local set_alt_texture = function(left, texture_index, preview_idx)
   end
 end
 
+-- Shows the name of the alt on a preview in its stage name pane. The game only sets the stage's
+-- name when the preview's panel changes, so alts without a name of their own put it back
+local show_alt_name = function(preview_index, panel_index, form_type, alt)
+    if is_alts_api_current == false then
+        return
+    end
+    local label = Alts.get_alt_name_label(panel_index, form_type, alt)
+    if label == nil then
+        return
+    end
+    local name_pane = root_view:get_parts(get_stage_preview_name(preview_index)):get_pane("set_txt_stage_name")
+    if name_pane ~= nil then
+        name_pane:set_text_message(label)
+    end
+end
+
+-- Cycles through the alts of the selected panel without the plugin's carousel, for plugins
+-- older than ALTS_API_VERSION. Returns the same values as Alts.next_alt
+local step_alt_in_script = function(preview, is_forward)
+    local count = Alts.get_panel_alt_count(current_selected_panel, preview.form_type_)
+    local selected = math.min(preview.selected_alt_, count)
+    if is_forward == true then
+        selected = selected == count and 0 or selected + 1
+    elseif is_forward == false then
+        selected = selected == 0 and count or selected - 1
+    end
+
+    local get_texture = function(alt)
+        local texture_idx = Alts.get_alt_texture_index(current_selected_panel, preview.form_type_, alt)
+        if texture_idx < 0 then
+            return nil
+        end
+        return texture_idx
+    end
+
+    local left_idx, right_idx = nil, nil
+    if count == 1 then
+        right_idx = get_texture(selected == 0 and 1 or 0)
+    elseif count > 1 then
+        left_idx = get_texture(selected == 0 and count or selected - 1)
+        right_idx = get_texture(selected == count and 0 or selected + 1)
+    end
+
+    return selected, get_texture(selected), left_idx, right_idx
+end
+
 local set_alt_panel_textures = function(is_forward)
     if current_selected_preview == UI_INVALID_INDEX then
         Alts.send_message("Can't change alt on invalid preview")
@@ -325,55 +379,29 @@ local set_alt_panel_textures = function(is_forward)
         return
     end
 
-    local count = Alts.get_panel_alt_count(current_selected_panel, preview.form_type_)
-
-    if is_forward == true then
-        if count == preview.selected_alt_ then
-            preview.selected_alt_ = 0
-        else
-            preview.selected_alt_ = preview.selected_alt_ + 1
-        end
+    -- the plugin owns the selection and its wraparound, we only mirror the selected alt. Whether
+    -- L/R only stop at favorites is up to stage_select.favorites_only in the plugin's config
+    local selected, texture_idx, left_idx, right_idx
+    if is_alts_api_current == false then
+        selected, texture_idx, left_idx, right_idx = step_alt_in_script(preview, is_forward)
+    elseif is_forward == true then
+        selected, texture_idx, left_idx, right_idx = Alts.next_alt(current_selected_preview, current_selected_panel, preview.form_type_)
     elseif is_forward == false then
-        if preview.selected_alt_ == 0 then
-            preview.selected_alt_ = count
-        else
-            preview.selected_alt_ = preview.selected_alt_ - 1
-        end
-    end
-
-    local texture_idx = Alts.get_alt_texture_index(current_selected_panel, preview.form_type_, preview.selected_alt_)
-
-    local parts_name = get_stage_preview_name(current_selected_preview)
-    local parts = root_view:get_parts(parts_name)
-
-    if count == 0 then
-      set_alt_texture(true, nil, current_selected_preview)
-      set_alt_texture(false, nil, current_selected_preview)
-    elseif count == 1 then
-      set_alt_texture(true, nil, current_selected_preview)
-
-      local idx = preview.selected_alt_ == 0 and 1 or 0
-      set_alt_texture(false, Alts.get_alt_texture_index(current_selected_panel, preview.form_type_, idx), current_selected_preview)
+        selected, texture_idx, left_idx, right_idx = Alts.prev_alt(current_selected_preview, current_selected_panel, preview.form_type_)
     else
-      local left_idx = preview.selected_alt_ == 0 and count or preview.selected_alt_ - 1
-      local right_idx = preview.selected_alt_ == count and 0 or preview.selected_alt_ + 1
-
-      set_alt_texture(
-        true,
-        Alts.get_alt_texture_index(current_selected_panel, preview.form_type_, left_idx),
-        current_selected_preview
-      )
-
-      set_alt_texture(
-        false,
-        Alts.get_alt_texture_index(current_selected_panel, preview.form_type_, right_idx),
-        current_selected_preview
-      )
+        selected, texture_idx, left_idx, right_idx = Alts.select_alt(current_selected_preview, current_selected_panel, preview.form_type_, preview.selected_alt_)
     end
+    preview.selected_alt_ = selected
+    show_alt_name(current_selected_preview, current_selected_panel, preview.form_type_, selected)
+
+    set_alt_texture(true, left_idx, current_selected_preview)
+    set_alt_texture(false, right_idx, current_selected_preview)
 
-    if texture_idx < 0 then
+    if texture_idx == nil then
         return
     end
+    local parts_name = get_stage_preview_name(current_selected_preview)
+    local parts = root_view:get_parts(parts_name)
     local pane_name = "set_rep_stage"
     if preview.form_type_ == STAGE_FORM_TYPE_BATTLE then
         pane_name = "set_rep_stage_battle"
@@ -385,6 +413,21 @@ local set_alt_panel_textures = function(is_forward)
     texture_pane:replace_texture(texture_idx)
 end
 
+local toggle_selected_alt_favorite = function()
+    if current_selected_preview == UI_INVALID_INDEX or current_selected_panel == UI_INVALID_INDEX then
+        return
+    end
+
+    if is_alts_api_current == false then
+        Alts.send_message("This version of stage alts doesn't support favorites")
+        return
+    end
+
+    local preview = stage_previews[current_selected_preview + 1]
+    local is_favorite = Alts.toggle_favorite(current_selected_panel, preview.form_type_, preview.selected_alt_)
+    Alts.send_message("Alt " .. tostring(preview.selected_alt_) .. " favorite: " .. tostring(is_favorite))
+end
+
 -- Gets the name of the stage icon part in the layout file
 -- CLOSURE_6, R66
 local get_stage_panel_name = function(stage_index)
@@ -578,11 +621,21 @@ local set_stage_preview_form = function(preview_index, stage_form)
             end
             return
         end
-        stage_previews[preview_index + 1].form_type_ = stage_form
+        local preview = stage_previews[preview_index + 1]
+        local form_changed = preview.form_type_ ~= stage_form
+        preview.form_type_ = stage_form
         UiScriptPlayer.invoke("set_stage_form_type_stage_preview", preview_index, stage_form)
         if preview_index == current_selected_preview then
             set_tab_form_text(stage_form)
         end
+
+        -- a new form starts over on its base stage, like the plugin's selection does
+        if form_changed then
+            preview.selected_alt_ = 0
+            if preview_index == current_selected_preview then
+                set_alt_panel_textures(nil)
+            end
+        end
     end
 end
 
@@ -604,6 +657,16 @@ local set_stage_preview_buttons_enable = function(preview_index, enable)
     preview:set_decidable(button_id + 1, enable)
 end
 
+-- Writes the alt picked on a preview into its BGM id, so the match loads it in the picked form
+local write_alt_field = function(preview_index)
+    local preview = stage_previews[preview_index + 1]
+    if is_alts_api_current == false then
+        Alts.write_alt_field_to_bgm_id(preview_index, preview.selected_alt_)
+        return
+    end
+    Alts.write_alt_field_to_bgm_id(preview_index, preview.form_type_, preview.selected_alt_)
+end
+
 -- Sets the stage preview based on the selected stage panel
 -- CLOSURE_26, R86
 local set_stage_preview_from_stage_panel = function(preview_index, panel_index)
@@ -613,7 +676,7 @@ local set_stage_preview_from_stage_panel = function(preview_index, panel_index)
 
     UiScriptPlayer.invoke("set_stage_preview_from_panel", preview_index, panel_index)
     set_stage_preview_form(preview_index, stage_previews[preview_index + 1].form_type_)
-    Alts.write_alt_field_to_bgm_id(preview_index, stage_previews[preview_index + 1].selected_alt_)
+    write_alt_field(preview_index)
 end
 
 -- Sets the stage preview based on the selected custom stage panel
@@ -1056,6 +1119,9 @@ local setup = function()
     virtual_input = layout_root:get_virtual_input()
     next_scene_animation = root_view:get_animation("anim_next_scene")
 
+    -- the plugin may be older than this script, see ALTS_API_VERSION
+    is_alts_api_current = Alts.api_version ~= nil and Alts.api_version() >= ALTS_API_VERSION
+
     for i=1, USE_STAGE_MAX, 1 do
         stage_previews[i] = StagePreview.new()
         medals[i] = Medal.new()
@@ -1153,9 +1219,20 @@ local setup = function()
     INPUT_STRIKE = VI_BUTTON_EXTRA29
     INPUT_ALT_R = VI_BUTTON_EXTRA28
     INPUT_ALT_L = VI_BUTTON_EXTRA27
+    INPUT_ALT_FAVORITE = VI_BUTTON_EXTRA26
     virtual_input:set_assign(INPUT_STRIKE, LIB_BUTTON_ZR, nil)
     virtual_input:set_assign(INPUT_ALT_R, LIB_BUTTON_R, nil)
     virtual_input:set_assign(INPUT_ALT_L, LIB_BUTTON_L, nil)
+
+    -- the favorite toggle is ZL by default, which nothing else on this stage select uses. It can
+    -- be moved or left unbound with stage_select.favorite_button in the plugin's config
+    local favorite_button = nil
+    if is_alts_api_current == true and Alts.favorite_button() ~= "" then
+        favorite_button = _ENV["LIB_BUTTON_" .. string.upper(Alts.favorite_button())]
+    end
+    if favorite_button ~= nil then
+        virtual_input:set_assign(INPUT_ALT_FAVORITE, favorite_button, nil)
+    end
 end
 
 -- Initializes the medal position, presumably at the beginning of the SSS load
@@ -2425,51 +2502,6 @@ local try_handle_exiting_scene = function()
     return true
 end
 
-local change_selected_alt = function(is_forward)
-    if current_selected_preview == UI_INVALID_INDEX then
-        Alts.send_message("Can't change alt on invalid preview")
-        return
-    end
-
-    local preview = stage_previews[current_selected_preview + 1]
-    if current_selected_panel == UI_INVALID_INDEX then
-        Alts.send_message("Preview's panel id is invalid")
-        return
-    end
-
-    local count = Alts.get_panel_alt_count(current_selected_panel, preview.form_type_)
-
-    if is_forward then
-        if count == preview.selected_alt_ then
-            preview.selected_alt_ = 0
-        else
-            preview.selected_alt_ = preview.selected_alt_ + 1
-        end
-    else
-        if preview.selected_alt_ == 0 then
-            preview.selected_alt_ = count
-        else
-            preview.selected_alt_ = preview.selected_alt_ - 1
-        end
-    end
-
-    local texture_idx = Alts.get_alt_texture_index(current_selected_panel, preview.form_type_, preview.selected_alt_)
-    if texture_idx < 0 then
-        return
-    end
-    local parts_name = get_stage_preview_name(current_selected_preview)
-    local parts = root_view:get_parts(parts_name)
-    local pane_name = "set_rep_stage"
-    if preview.form_type_ == STAGE_FORM_TYPE_BATTLE then
-        pane_name = "set_rep_stage_battle"
-    elseif preview.form_type_ == STAGE_FORM_TYPE_END then
-        pane_name = "set_rep_stage_end"
-    end
-
-    local texture_pane = parts:get_pane(pane_name)
-    texture_pane:replace_texture(texture_idx)
-end
-
 -- CLOSURE_80, R139
 local regular_main_update = function()
     set_scene_enable(true)
@@ -2595,6 +2627,8 @@ local regular_main_update = function()
                         set_alt_panel_textures(false)
                     elseif virtual_input:is_pressed(INPUT_ALT_R) then
                         set_alt_panel_textures(true)
+                    elseif virtual_input:is_pressed(INPUT_ALT_FAVORITE) then
+                        toggle_selected_alt_favorite()
                     else
                         update_both_tabs()
                     end
@@ -2724,21 +2758,21 @@ main = function()
         first.alt_ = stage_previews[1].selected_alt_
         first.panel_ = stage_previews[1].panel_id_
         first.form_ = stage_previews[1].form_type_
-        Alts.write_alt_field_to_bgm_id(0, first.alt_)
+        write_alt_field(0)
     end
 
     if USE_STAGE_NUM > 1 then
         second.alt_ = stage_previews[2].selected_alt_
         second.panel_ = stage_previews[2].panel_id_
         second.form_ = stage_previews[2].form_type_
-        Alts.write_alt_field_to_bgm_id(1, second.alt_)
+        write_alt_field(1)
     end
 
     if USE_STAGE_NUM > 2 then
         third.alt_ = stage_previews[3].selected_alt_
         third.panel_ = stage_previews[3].panel_id_
         third.form_ = stage_previews[3].form_type_
-        Alts.write_alt_field_to_bgm_id(2, third.alt_)
+        write_alt_field(2)
     end
 
     Alts.set_alts(
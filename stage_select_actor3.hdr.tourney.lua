@@ -82,6 +82,13 @@ local INPUT_STRIKE = VI_BUTTON_EXTRA29
 local INPUT_ALT_R = VI_BUTTON_EXTRA28
 local INPUT_ALT_L = VI_BUTTON_EXTRA27
 
+-- The Alts.api_version this script is written against. Plugins older than that only count alts
+-- and look up their textures, so the script cycles through alts by itself on them
+local ALTS_API_VERSION = 6
+
+-- Whether the plugin supports ALTS_API_VERSION, checked once in setup
+local is_alts_api_current = false
+
 exit_code_ = nil
 
 -- The layout root, gotten from the LayoutRootList with the index specified
@@ -485,26 +492,6 @@ local disable_stage_preview = function(preview_index)
     preview.is_sub_stage_ = false
 end
 
--- Sets the stage form of the specified preview
--- CLOSURE_24, R84
-local set_stage_preview_form = function(preview_index, stage_form)
-    if preview_index ~= UI_INVALID_INDEX then
-        if UiScriptPlayer.invoke("is_fixed_form_type_stage_preview", preview_index) == true then
-            local training_form = UiScriptPlayer.invoke("get_stage_fixed_form_type", preview_index)
-            UiScriptPlayer.invoke("set_stage_form_type_stage_preview", preview_index, training_form)
-            if preview_index == current_selected_preview then
-                set_tab_form_text(training_form)
-            end
-            return
-        end
-        stage_previews[preview_index + 1].form_type_ = stage_form
-        UiScriptPlayer.invoke("set_stage_form_type_stage_preview", preview_index, stage_form)
-        if preview_index == current_selected_preview then
-            set_tab_form_text(stage_form)
-        end
-    end
-end
-
 local set_alt_texture = function(left, texture_index, preview_idx)
   local base_alt_name = left and "alt_l" or "alt_r"
   local texture_name = left and "set_rep_alt_l" or "set_rep_alt_r"
@@ -522,6 +509,52 @@ local set_alt_texture = function(left, texture_index, preview_idx)
   end
 end
 
+-- Shows the name of the alt on a preview in its stage name pane. The game only sets the stage's
+-- name when the preview's panel changes, so alts without a name of their own put it back
+local show_alt_name = function(preview_index, panel_index, form_type, alt)
+    if is_alts_api_current == false then
+        return
+    end
+    local label = Alts.get_alt_name_label(panel_index, form_type, alt)
+    if label == nil then
+        return
+    end
+    local name_pane = root_view:get_parts(get_stage_preview_name(preview_index)):get_pane("set_txt_stage_name")
+    if name_pane ~= nil then
+        name_pane:set_text_message(label)
+    end
+end
+
+-- Cycles through the alts of the selected panel without the plugin's carousel, for plugins
+-- older than ALTS_API_VERSION. Returns the same values as Alts.next_alt
+local step_alt_in_script = function(preview, is_forward)
+    local count = Alts.get_panel_alt_count(current_selected_panel, preview.form_type_)
+    local selected = math.min(preview.selected_alt_, count)
+    if is_forward == true then
+        selected = selected == count and 0 or selected + 1
+    elseif is_forward == false then
+        selected = selected == 0 and count or selected - 1
+    end
+
+    local get_texture = function(alt)
+        local texture_idx = Alts.get_alt_texture_index(current_selected_panel, preview.form_type_, alt)
+        if texture_idx < 0 then
+            return nil
+        end
+        return texture_idx
+    end
+
+    local left_idx, right_idx = nil, nil
+    if count == 1 then
+        right_idx = get_texture(selected == 0 and 1 or 0)
+    elseif count > 1 then
+        left_idx = get_texture(selected == 0 and count or selected - 1)
+        right_idx = get_texture(selected == count and 0 or selected + 1)
+    end
+
+    return selected, get_texture(selected), left_idx, right_idx
+end
+
 local set_alt_panel_textures = function(is_forward)
     if current_selected_preview == UI_INVALID_INDEX then
         Alts.send_message("Can't change alt on invalid preview")
@@ -536,55 +569,29 @@ local set_alt_panel_textures = function(is_forward)
         return
     end
 
-    local count = Alts.get_panel_alt_count(current_selected_panel, preview.form_type_)
-
-    if is_forward == true then
-        if count == preview.selected_alt_ then
-            preview.selected_alt_ = 0
-        else
-            preview.selected_alt_ = preview.selected_alt_ + 1
-        end
+    -- the plugin owns the selection and its wraparound, we only mirror the selected alt. Whether
+    -- L/R only stop at favorites is up to stage_select.favorites_only in the plugin's config
+    local selected, texture_idx, left_idx, right_idx
+    if is_alts_api_current == false then
+        selected, texture_idx, left_idx, right_idx = step_alt_in_script(preview, is_forward)
+    elseif is_forward == true then
+        selected, texture_idx, left_idx, right_idx = Alts.next_alt(current_selected_preview, current_selected_panel, preview.form_type_)
     elseif is_forward == false then
-        if preview.selected_alt_ == 0 then
-            preview.selected_alt_ = count
-        else
-            preview.selected_alt_ = preview.selected_alt_ - 1
-        end
-    end
-
-    local texture_idx = Alts.get_alt_texture_index(current_selected_panel, preview.form_type_, preview.selected_alt_)
-
-    local parts_name = get_stage_preview_name(current_selected_preview)
-    local parts = root_view:get_parts(parts_name)
-
-    if count == 0 then
-      set_alt_texture(true, nil, current_selected_preview)
-      set_alt_texture(false, nil, current_selected_preview)
-    elseif count == 1 then
-      set_alt_texture(true, nil, current_selected_preview)
-
-      local idx = preview.selected_alt_ == 0 and 1 or 0
-      set_alt_texture(false, Alts.get_alt_texture_index(current_selected_panel, preview.form_type_, idx), current_selected_preview)
+        selected, texture_idx, left_idx, right_idx = Alts.prev_alt(current_selected_preview, current_selected_panel, preview.form_type_)
     else
-      local left_idx = preview.selected_alt_ == 0 and count or preview.selected_alt_ - 1
-      local right_idx = preview.selected_alt_ == count and 0 or preview.selected_alt_ + 1
-
-      set_alt_texture(
-        true,
-        Alts.get_alt_texture_index(current_selected_panel, preview.form_type_, left_idx),
-        current_selected_preview
-      )
-
-      set_alt_texture(
-        false,
-        Alts.get_alt_texture_index(current_selected_panel, preview.form_type_, right_idx),
-        current_selected_preview
-      )
+        selected, texture_idx, left_idx, right_idx = Alts.select_alt(current_selected_preview, current_selected_panel, preview.form_type_, preview.selected_alt_)
     end
+    preview.selected_alt_ = selected
+    show_alt_name(current_selected_preview, current_selected_panel, preview.form_type_, selected)
+
+    set_alt_texture(true, left_idx, current_selected_preview)
+    set_alt_texture(false, right_idx, current_selected_preview)
 
-    if texture_idx < 0 then
+    if texture_idx == nil then
         return
     end
+    local parts_name = get_stage_preview_name(current_selected_preview)
+    local parts = root_view:get_parts(parts_name)
     local pane_name = "set_rep_stage"
     if preview.form_type_ == STAGE_FORM_TYPE_BATTLE then
         pane_name = "set_rep_stage_battle"
@@ -596,7 +603,35 @@ local set_alt_panel_textures = function(is_forward)
     texture_pane:replace_texture(texture_idx)
 end
 
+-- Sets the stage form of the specified preview
+-- CLOSURE_24, R84
+local set_stage_preview_form = function(preview_index, stage_form)
+    if preview_index ~= UI_INVALID_INDEX then
+        if UiScriptPlayer.invoke("is_fixed_form_type_stage_preview", preview_index) == true then
+            local training_form = UiScriptPlayer.invoke("get_stage_fixed_form_type", preview_index)
+            UiScriptPlayer.invoke("set_stage_form_type_stage_preview", preview_index, training_form)
+            if preview_index == current_selected_preview then
+                set_tab_form_text(training_form)
+            end
+            return
+        end
+        local preview = stage_previews[preview_index + 1]
+        local form_changed = preview.form_type_ ~= stage_form
+        preview.form_type_ = stage_form
+        UiScriptPlayer.invoke("set_stage_form_type_stage_preview", preview_index, stage_form)
+        if preview_index == current_selected_preview then
+            set_tab_form_text(stage_form)
+        end
 
+        -- a new form starts over on its base stage, like the plugin's selection does
+        if form_changed then
+            preview.selected_alt_ = 0
+            if preview_index == current_selected_preview then
+                set_alt_panel_textures(nil)
+            end
+        end
+    end
+end
 
 -- Enables/disables the stage form/music buttons for the specified preview
 -- CLOSURE_25, R85
@@ -1111,6 +1146,14 @@ local setup = function()
     virtual_input = layout_root:get_virtual_input()
     next_scene_animation = root_view:get_animation("anim_next_scene")
 
+    -- the plugin may be older than this script, see ALTS_API_VERSION
+    is_alts_api_current = Alts.api_version ~= nil and Alts.api_version() >= ALTS_API_VERSION
+
+    -- only offer alts that are tagged as tournament legal
+    if is_alts_api_current == true then
+        Alts.set_tag_filter("competitive")
+    end
+
     -- set up the previews (these are the 1-3 big previews on SSS)
     for i=1, USE_STAGE_MAX, 1 do
         stage_previews[i] = StagePreview.new()
@@ -1383,6 +1426,12 @@ end
 local cancel = function()
     exit_code_ = SCENE_EXIT_CODE_CANCEL
     UiSoundManager.play_se(UI_SE_ID_CANCEL)
+
+    -- the competitive filter only lasts while this stage select is open
+    if is_alts_api_current == true then
+        Alts.set_tag_filter(nil)
+    end
+
     if UiScriptPlayer.invoke("is_fixed_form_type_stage_preview", current_selected_preview) == true then
         -- pretty sure this is a bug lol
         -- this variable is mentioned nowhere else (preview_id)
@@ -2479,53 +2528,6 @@ local try_handle_exiting_scene = function()
     return true
 end
 
-local change_selected_alt = function(is_forward)
-    if current_selected_preview == UI_INVALID_INDEX then
-        Alts.send_message("Can't change alt on invalid preview")
-        return
-    end
-
-    local preview = stage_previews[current_selected_preview + 1]
-    if current_selected_panel == UI_INVALID_INDEX then
-        Alts.send_message("Preview's panel id is invalid")
-        return
-    end
-
-    local count = Alts.get_panel_alt_count(current_selected_panel, preview.form_type_)
-
-    if is_forward then
-        if count == preview.selected_alt_ then
-            preview.selected_alt_ = 0
-        else
-            preview.selected_alt_ = preview.selected_alt_ + 1
-        end
-    else
-        if preview.selected_alt_ == 0 then
-            preview.selected_alt_ = count
-        else
-            preview.selected_alt_ = preview.selected_alt_ - 1
-        end
-    end
-
-    local texture_idx = Alts.get_alt_texture_index(current_selected_panel, preview.form_type_, preview.selected_alt_)
-    if texture_idx < 0 then
-        return
-    end
-    local parts_name = get_stage_preview_name(current_selected_preview)
-    local parts = root_view:get_parts(parts_name)
-    local pane_name = "set_rep_stage"
-    if preview.form_type_ == STAGE_FORM_TYPE_BATTLE then
-        pane_name = "set_rep_stage_battle"
-    elseif preview.form_type_ == STAGE_FORM_TYPE_END then
-        pane_name = "set_rep_stage_end"
-    end
-
-    local texture_pane = parts:get_pane(pane_name)
-    texture_pane:replace_texture(texture_idx)
-end
-
-
-
 -- CLOSURE_80, R139
 local regular_main_update = function()
     set_scene_enable(true)
@@ -81,6 +81,13 @@ local SCENE_STATE_SHOULD_EXIT    = 1 -- R23
 local SCENE_STATE_EXITING        = 2 -- R24
 local SCENE_STATE_EXITED         = 3 -- R25
 
+-- The Alts.api_version this script is written against. Plugins older than that only count alts
+-- and look up their textures, so the script cycles through alts by itself on them
+local ALTS_API_VERSION = 6
+
+-- Whether the plugin supports ALTS_API_VERSION, checked once in setup
+local is_alts_api_current = false
+
 exit_code_                       = nil
 
 -- The layout root, gotten from the LayoutRootList with the index specified
@@ -435,6 +442,34 @@ local set_main_button_active = function(button_id, active)
     root_button_selector:set_decidable(button_id, active)
 end
 
+-- Shows the name of the alt on a preview in its stage name pane. The game only sets the stage's
+-- name when the preview's panel changes, so alts without a name of their own put it back
+local show_alt_name = function(preview_index, panel_index, form_type, alt)
+    if is_alts_api_current == false then
+        return
+    end
+    local label = Alts.get_alt_name_label(panel_index, form_type, alt)
+    if label == nil then
+        return
+    end
+    local name_pane = root_view:get_parts(get_stage_preview_name(preview_index)):get_pane("set_txt_stage_name")
+    if name_pane ~= nil then
+        name_pane:set_text_message(label)
+    end
+end
+
+-- Starts the alt selection of a preview over on the base stage of the panel it shows. The plugin
+-- keeps its own copy of the selection, so it has to hear about every change
+local reset_selected_alt = function(preview_index, panel_index)
+    local preview = stage_previews[preview_index + 1]
+    if is_alts_api_current == false then
+        preview.selected_alt_ = 0
+        return
+    end
+    preview.selected_alt_ = Alts.select_alt(preview_index, panel_index, preview.form_type_, 0)
+    show_alt_name(preview_index, panel_index, preview.form_type_, preview.selected_alt_)
+end
+
 -- Enables the specified stage preview
 -- CLOSURE_22, R82
 local enable_stage_preview = function(preview_index, panel_id, is_sub_stage)
@@ -451,6 +486,7 @@ local disable_stage_preview = function(preview_index)
     preview.enable_ = false
     preview.panel_id_ = UI_INVALID_INDEX
     preview.is_sub_stage_ = false
+    reset_selected_alt(preview_index, UI_INVALID_INDEX)
 end
 
 -- Sets the stage form of the specified preview
@@ -465,11 +501,21 @@ local set_stage_preview_form = function(preview_index, stage_form)
             end
             return
         end
-        stage_previews[preview_index + 1].form_type_ = stage_form
+        local preview = stage_previews[preview_index + 1]
+        local form_changed = preview.form_type_ ~= stage_form
+        preview.form_type_ = stage_form
         UiScriptPlayer.invoke("set_stage_form_type_stage_preview", preview_index, stage_form)
         if preview_index == current_selected_preview then
             set_tab_form_text(stage_form)
         end
+
+        if form_changed then
+            local panel_index = current_selected_panel
+            if preview.enable_ == true then
+                panel_index = preview.panel_id_
+            end
+            reset_selected_alt(preview_index, panel_index)
+        end
     end
 end
 
@@ -500,6 +546,7 @@ local set_stage_preview_from_stage_panel = function(preview_index, panel_index)
 
     UiScriptPlayer.invoke("set_stage_preview_from_panel", preview_index, panel_index)
     set_stage_preview_form(preview_index, stage_previews[preview_index + 1].form_type_)
+    reset_selected_alt(preview_index, panel_index)
 end
 
 -- Sets the stage preview based on the selected custom stage panel
@@ -510,6 +557,9 @@ local set_stage_preview_from_sub_stage_panel = function(preview_index, panel_ind
     end
 
     UiScriptPlayer.invoke("set_stage_preview_from_sub_panel", preview_index, panel_index)
+
+    -- custom stages have no alts
+    reset_selected_alt(preview_index, UI_INVALID_INDEX)
 end
 
 -- Plays the stage form switch animation
@@ -885,6 +935,9 @@ local setup = function()
     virtual_input = layout_root:get_virtual_input()
     next_scene_animation = root_view:get_animation("anim_next_scene")
 
+    -- the plugin may be older than this script, see ALTS_API_VERSION
+    is_alts_api_current = Alts.api_version ~= nil and Alts.api_version() >= ALTS_API_VERSION
+
     for i = 1, USE_STAGE_MAX, 1 do
         stage_previews[i] = StagePreview.new()
         medals[i] = Medal.new()
@@ -2058,6 +2111,24 @@ local try_handle_exiting_scene = function()
     return true
 end
 
+-- Cycles through the alts of the selected panel without the plugin's carousel, for plugins
+-- older than ALTS_API_VERSION. Returns the same values as Alts.next_alt
+local step_alt_in_script = function(preview, is_forward)
+    local count = Alts.get_panel_alt_count(current_selected_panel, preview.form_type_)
+    local selected = math.min(preview.selected_alt_, count)
+    if is_forward then
+        selected = selected == count and 0 or selected + 1
+    else
+        selected = selected == 0 and count or selected - 1
+    end
+
+    local texture_idx = Alts.get_alt_texture_index(current_selected_panel, preview.form_type_, selected)
+    if texture_idx < 0 then
+        return selected, nil
+    end
+    return selected, texture_idx
+end
+
 local change_selected_alt = function(is_forward)
     if current_selected_preview == UI_INVALID_INDEX then
         Alts.send_message("Can't change alt on invalid preview")
@@ -2070,24 +2141,19 @@ local change_selected_alt = function(is_forward)
         return
     end
 
-    local count = Alts.get_panel_alt_count(current_selected_panel, preview.form_type_)
-
-    if is_forward then
-        if count == preview.selected_alt_ then
-            preview.selected_alt_ = 0
-        else
-            preview.selected_alt_ = preview.selected_alt_ + 1
-        end
+    -- whether this only stops at favorites is up to stage_select.favorites_only in the plugin's config
+    local selected, texture_idx
+    if is_alts_api_current == false then
+        selected, texture_idx = step_alt_in_script(preview, is_forward)
+    elseif is_forward then
+        selected, texture_idx = Alts.next_alt(current_selected_preview, current_selected_panel, preview.form_type_)
     else
-        if preview.selected_alt_ == 0 then
-            preview.selected_alt_ = count
-        else
-            preview.selected_alt_ = preview.selected_alt_ - 1
-        end
+        selected, texture_idx = Alts.prev_alt(current_selected_preview, current_selected_panel, preview.form_type_)
     end
+    preview.selected_alt_ = selected
+    show_alt_name(current_selected_preview, current_selected_panel, preview.form_type_, selected)
 
-    local texture_idx = Alts.get_alt_texture_index(current_selected_panel, preview.form_type_, preview.selected_alt_)
-    if texture_idx < 0 then
+    if texture_idx == nil then
         return
     end
     local parts_name = get_stage_preview_name(current_selected_preview)